@@ -1,10 +1,11 @@
 use anyhow::Result;
 use chrono::{DateTime, Duration, FixedOffset};
 use reqwest::blocking::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tracing::{debug, warn};
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct LatLon {
     pub lat: f32,
     pub lon: f32
@@ -15,7 +16,7 @@ struct CurrentWeatherResponse {
     weather: CurrentWeather
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CurrentWeather {
     /// ISO 8601-formatted timestamp of this weather record
     #[serde(with = "date_serde")]
@@ -158,7 +159,7 @@ struct HourlyWeatherResponse {
     weather: Vec<HourlyWeather>
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HourlyWeather {
     /// ISO 8601-formatted timestamp of this weather record
     #[serde(with = "date_serde")]
@@ -254,7 +255,47 @@ pub struct HourlyWeather {
     pub fallback_source_ids: Option<Value>
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+impl From<HourlyWeather> for CurrentWeather {
+    /// Use an hourly record as current weather, e.g. for points in time the current weather endpoint doesn't cover.
+    /// The hourly values are used for all 10, 30 and 60 minute fields.
+    fn from(weather: HourlyWeather) -> Self {
+        CurrentWeather {
+            timestamp: weather.timestamp,
+            source_id: weather.source_id,
+            cloud_cover: weather.cloud_cover,
+            condition: weather.condition,
+            dew_point: weather.dew_point,
+            icon: weather.icon,
+            precipitation_10: weather.precipitation,
+            precipitation_30: weather.precipitation,
+            precipitation_60: weather.precipitation,
+            pressure_msl: weather.pressure_msl,
+            relative_humidity: weather.relative_humidity,
+            solar_10: weather.solar,
+            solar_30: weather.solar,
+            solar_60: weather.solar,
+            sunshine_30: weather.sunshine,
+            sunshine_60: weather.sunshine,
+            temperature: weather.temperature,
+            visibility: weather.visibility,
+            wind_direction_10: weather.wind_direction,
+            wind_direction_30: weather.wind_direction,
+            wind_direction_60: weather.wind_direction,
+            wind_speed_10: weather.wind_speed,
+            wind_speed_30: weather.wind_speed,
+            wind_speed_60: weather.wind_speed,
+            wind_gust_direction_10: weather.wind_gust_direction,
+            wind_gust_direction_30: weather.wind_gust_direction,
+            wind_gust_direction_60: weather.wind_gust_direction,
+            wind_gust_speed_10: weather.wind_gust_speed,
+            wind_gust_speed_30: weather.wind_gust_speed,
+            wind_gust_speed_60: weather.wind_gust_speed,
+            fallback_source_ids: weather.fallback_source_ids,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Condition {
     #[serde(rename = "dry")]
     Dry,
@@ -274,7 +315,7 @@ pub enum Condition {
     Null,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Icon {
     #[serde(rename = "clear-day")]
     ClearDay,
@@ -314,6 +355,7 @@ pub fn get_current_weather(position: &LatLon, time_zone: &chrono_tz::Tz) -> Resu
         ("lon", &format!("{}", position.lon)),
         ("tz", time_zone.name())
     ];
+    debug!(lat = position.lat, lon = position.lon, "Getting current weather");
     let response: CurrentWeatherResponse = Client::new().get(CURRENT_WEATHER_URL).query(query).send()?.json()?;
     Ok(response.weather)
 }
@@ -329,6 +371,7 @@ pub fn get_hourly_weather(date_time: &DateTime<FixedOffset>, position: &LatLon,
         ("lon", &format!("{}", position.lon)),
         ("tz", time_zone.name())
     ];
+    debug!(%date, lat = position.lat, lon = position.lon, "Getting hourly weather");
     let response: HourlyWeatherResponse = Client::new().get(HOURLY_WEATHER_URL).query(query).send()?.json()?;
     Ok(response.weather.into_iter().take(24).collect()) // returns 25 records but we want only 24
 }
@@ -343,8 +386,9 @@ pub fn get_weather_forecast(date_time: &DateTime<FixedOffset>, days: i64, positi
         let date = *date_time + Duration::days(i);
         let result = get_hourly_weather(&date, position, time_zone);
 
-        if let Ok(mut weather) = result {
-            forecast.append(&mut weather);
+        match result {
+            Ok(mut weather) => forecast.append(&mut weather),
+            Err(e) => warn!(date = %date.date_naive(), error = %format!("{:#}", e), "Couldn't get hourly weather for the forecast"),
         }
     }
     forecast
@@ -352,7 +396,14 @@ pub fn get_weather_forecast(date_time: &DateTime<FixedOffset>, days: i64, positi
 
 mod date_serde {
     use chrono::{DateTime, FixedOffset};
-    use serde::{self, Deserialize, Deserializer};
+    use serde::{self, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(date_time: &DateTime<FixedOffset>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        serializer.serialize_str(&date_time.to_rfc3339())
+    }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<FixedOffset>, D::Error>
         where
@@ -375,6 +426,7 @@ mod tests {
     const TIME_ZONE: chrono_tz::Tz = chrono_tz::Europe::Berlin;
 
     #[test]
+    #[ignore = "needs access to api.brightsky.dev, run with --ignored"]
     fn test_current_weather() {
         let weather = get_current_weather(&POSITION, &TIME_ZONE);
 
@@ -383,6 +435,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs access to api.brightsky.dev, run with --ignored"]
     fn test_hourly_weather() {
         let date_time: DateTime<FixedOffset> = chrono::Local::now().into();
         let weather_records = get_hourly_weather(&date_time, &POSITION, &TIME_ZONE);
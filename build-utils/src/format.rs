@@ -0,0 +1,164 @@
+//! Config files in JSON with comments (JSON5), TOML or YAML. The format is chosen by the file extension.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// File names that are searched for a config, in this order
+pub const CONFIG_FILE_NAMES: &[&str] = &["config.json", "config.jsonc", "config.json5", "config.toml", "config.yaml", "config.yml"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// JSON, JSON with comments and JSON5
+    Json5,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        match extension.as_str() {
+            "json" | "jsonc" | "json5" => Ok(ConfigFormat::Json5),
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            _ => bail!("Unknown config format of {}. Use .json, .jsonc, .json5, .toml, .yaml or .yml", path.display()),
+        }
+    }
+}
+
+/// Position in a config file, both starting at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The config couldn't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub location: Option<Location>,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some(Location { line, column }) => write!(f, "line {}, column {}: {}", line, column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse the text of a config file
+pub fn parse(text: &str, format: ConfigFormat) -> Result<Value, ParseError> {
+    match format {
+        ConfigFormat::Json5 => json5::from_str(text).map_err(|e| match e {
+            json5::Error::Message { msg, location } => ParseError {
+                // Syntax errors contain a snippet of the text before the actual message
+                message: msg.rsplit_once("\n  = ").map_or(msg.clone(), |(_, message)| message.to_string()),
+                location: location.map(|l| Location { line: l.line, column: l.column }),
+            },
+        }),
+        ConfigFormat::Toml => toml::from_str(text).map_err(|e| ParseError {
+            message: e.message().to_string(),
+            location: e.span().map(|span| location_of(text, span.start)),
+        }),
+        ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| ParseError {
+            // The message of serde_yaml already ends with the location
+            message: e.to_string().split(" at line ").next().unwrap_or_default().to_string(),
+            location: e.location().map(|l| Location { line: l.line(), column: l.column() }),
+        }),
+    }
+}
+
+/// Read and parse a config file
+pub fn read(path: &Path) -> Result<Value> {
+    let format = ConfigFormat::from_path(path)?;
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let config = parse(&text, format).with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(config)
+}
+
+/// Find the first config file of [`CONFIG_FILE_NAMES`] in the directory
+pub fn find(dir: &Path) -> Option<PathBuf> {
+    CONFIG_FILE_NAMES.iter().map(|name| dir.join(name)).find(|path| path.exists())
+}
+
+/// Line and column of the byte offset
+fn location_of(text: &str, offset: usize) -> Location {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Location {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn expected() -> Value {
+        json!({
+            "timezone": "Europe/Berlin",
+            "location": { "lat": 52.5, "lon": 13 },
+            "weekday_names": { "monday": "Montag" }
+        })
+    }
+
+    #[test]
+    fn test_formats() {
+        let jsonc = r#"{
+            // Comments and trailing commas are fine
+            "timezone": "Europe/Berlin",
+            "location": { "lat": 52.5, "lon": 13 },
+            weekday_names: { monday: 'Montag', },
+        }"#;
+        assert_eq!(parse(jsonc, ConfigFormat::Json5).unwrap(), expected());
+
+        let toml = r#"
+            timezone = "Europe/Berlin" # Comment
+            location = { lat = 52.5, lon = 13 }
+
+            [weekday_names]
+            monday = "Montag"
+        "#;
+        assert_eq!(parse(toml, ConfigFormat::Toml).unwrap(), expected());
+
+        let yaml = "
+timezone: Europe/Berlin # Comment
+location:
+  lat: 52.5
+  lon: 13
+weekday_names:
+  monday: Montag
+";
+        assert_eq!(parse(yaml, ConfigFormat::Yaml).unwrap(), expected());
+    }
+
+    #[test]
+    fn test_error_location() {
+        let error = parse("{\n  \"timezone\": @\n}", ConfigFormat::Json5).unwrap_err();
+        assert_eq!(error.location, Some(Location { line: 2, column: 15 }));
+        assert_eq!(error.message, "expected array, boolean, null, number, object, or string");
+
+        let error = parse("timezone = \"Europe/Berlin\"\nlocation = \n", ConfigFormat::Toml).unwrap_err();
+        assert_eq!(error.location.map(|l| l.line), Some(2));
+
+        let error = parse("timezone: Europe/Berlin\nlocation: [\n", ConfigFormat::Yaml).unwrap_err();
+        assert_eq!(error.location.map(|l| l.line), Some(3));
+        assert!(error.to_string().starts_with("line 3, column"));
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ConfigFormat::from_path(Path::new("config.jsonc")).unwrap(), ConfigFormat::Json5);
+        assert_eq!(ConfigFormat::from_path(Path::new("/etc/renderer/config.YML")).unwrap(), ConfigFormat::Yaml);
+        assert!(ConfigFormat::from_path(Path::new("config.ini")).is_err());
+    }
+}
@@ -1,19 +1,59 @@
 use std::env;
 use std::fs::File;
-use std::io::{Write, Read};
+use std::io::Write;
 use std::path::Path;
 use anyhow::{Context, Result};
 
-pub fn get_config() -> Result<serde_json::Value> {
-    // Read the JSON configuration file
-    let mut config_file = File::open("config.json").context("Failed to open config.json")?;
-    let mut config_contents = String::new();
-    config_file.read_to_string(&mut config_contents).context("Failed to read config.json")?;
+pub mod format;
+pub mod overrides;
+pub mod schedule;
+pub mod shared;
+pub mod validate;
+
+/// Read the config of a package ([`shared::RENDERER`] or [`shared::ESP`]) and resolve it with the environment.
+///
+/// The config is the package's own config file (see [`format::CONFIG_FILE_NAMES`])
+/// or the package's section of the shared config in the workspace root (see [`shared`]).
+/// Overrides use the uppercase section name as prefix, e.g. `ESP__WIFI__PSK`. See [`overrides`] for the order values are applied in.
+pub fn get_config(section: &str) -> Result<serde_json::Value> {
+    let env_prefix = section.to_uppercase();
+    let environment = overrides::Environment::from_process();
+
+    let mut config = match format::find(Path::new(".")) {
+        Some(path) => {
+            let mut config = format::read(&path)?;
+            rerun_if_env_changed(&config, &env_prefix);
+            overrides::resolve_placeholders(&mut config, &environment)?;
+            config
+        }
+        None => {
+            let path = format::find(Path::new(".."))
+                .with_context(|| format!("No config file found. Create one of: {}", format::CONFIG_FILE_NAMES.join(", ")))?;
+            let mut shared = format::read(&path)?;
+            rerun_if_env_changed(&shared, &env_prefix);
+
+            // Placeholders are resolved first, so that the ESP's image_url is derived from the actual values
+            shared::resolve_placeholders(&mut shared, section, &environment)?;
+            let section = shared::section(&shared, section).with_context(|| format!("Invalid shared config {}", path.display()))?;
+            for warning in &section.warnings {
+                println!("cargo:warning={}", warning);
+            }
+            rerun_if_env_changed(&section.config, &env_prefix);
+            section.config
+        }
+    };
+
+    overrides::apply_overrides(&mut config, &env_prefix, &environment)?;
 
-    let config: serde_json::Value = serde_json::from_str(&config_contents).context("Failed to parse config.json")?;
     Ok(config)
 }
 
+fn rerun_if_env_changed(config: &serde_json::Value, env_prefix: &str) {
+    for name in overrides::variable_names(config, env_prefix) {
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
+}
+
 pub fn write_code(code: &str) -> Result<()> {
     let out_dir = env::var("OUT_DIR")?;
     let dest_path = Path::new(&out_dir).join("config.rs");
@@ -21,7 +61,11 @@ pub fn write_code(code: &str) -> Result<()> {
         .write_all(code.as_bytes())
         .context("Failed to write generated code")?;
     
-    println!("cargo:rerun-if-changed=config.json");
+    // Own config files and the shared config in the workspace root
+    for name in format::CONFIG_FILE_NAMES {
+        println!("cargo:rerun-if-changed={}", name);
+        println!("cargo:rerun-if-changed=../{}", name);
+    }
     Ok(())
 }
 
@@ -37,13 +81,37 @@ pub fn get_f64(value: &serde_json::Value, key: &str) -> Result<f64> {
     Ok(val)
 }
 
+/// Get a string value from a JSON object or `None` if the field doesn't exist
+pub fn get_str_opt(value: &serde_json::Value, key: &str) -> Result<Option<String>> {
+    if get_value(value, key).is_err() {
+        return Ok(None);
+    }
+    get_str(value, key).map(Some)
+}
+
+/// Get a string value from a JSON object or the default if the field doesn't exist
+pub fn get_str_or(value: &serde_json::Value, key: &str, default: &str) -> Result<String> {
+    if get_value(value, key).is_err() {
+        return Ok(default.to_string());
+    }
+    get_str(value, key)
+}
+
+/// Get a float value from a JSON object or the default if the field doesn't exist
+pub fn get_f64_or(value: &serde_json::Value, key: &str, default: f64) -> Result<f64> {
+    if get_value(value, key).is_err() {
+        return Ok(default);
+    }
+    get_f64(value, key)
+}
+
 /// Get a nested value from a JSON object
 /// 
 /// Example: You can use `get_value(&config, "weather_conditions.rain")` instead of `config["weather_conditions"]["rain"]`
 fn get_value<'a>(value: &'a serde_json::Value, key: &'a str) -> Result<&'a serde_json::Value> {
     let path: Vec<&str> = key.split('.').collect();
-    let val = path.iter().try_fold(value, |acc, &key| {
-        acc.get(key).context(format!("Field not found: {}", key))
+    let val = path.iter().try_fold(value, |acc, &field| {
+        acc.get(field).context(format!("Field not found: {}", key))
     })?;
     Ok(val)
 }
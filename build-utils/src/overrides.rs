@@ -0,0 +1,301 @@
+//! Resolve config values from the environment, so that secrets don't have to be stored in `config.json`.
+//!
+//! Values are applied in this order, later ones win:
+//!
+//! 1. The value in the config file
+//! 2. `${NAME}` placeholders in strings, read from the variable `NAME` or the file in `NAME_FILE`.
+//!    `${NAME:-default}` falls back to the default if neither is set and `$${` is a literal `${`.
+//! 3. Variables named after the prefix and the path of the field, separated by `__`,
+//!    e.g. `RENDERER__WEBDAV_AUTH__PASSWORD` or `RENDERER__WEBDAV_AUTH__PASSWORD_FILE` for `webdav_auth.password`.
+//!
+//! Setting both `NAME` and `NAME_FILE` is an error. Files (e.g. Docker secrets) are read without trailing newlines.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::validate::join_path;
+
+const FILE_SUFFIX: &str = "_FILE";
+const SEPARATOR: &str = "__";
+
+/// Environment variables the config is resolved from
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    vars: BTreeMap<String, String>,
+}
+
+impl Environment {
+    /// Variables of the current process
+    pub fn from_process() -> Self {
+        Environment { vars: env::vars().collect() }
+    }
+
+    pub fn from_vars<K: Into<String>, V: Into<String>>(vars: impl IntoIterator<Item = (K, V)>) -> Self {
+        Environment { vars: vars.into_iter().map(|(k, v)| (k.into(), v.into())).collect() }
+    }
+
+    /// Get the variable `name` or the content of the file in `name_FILE`
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        let file_name = format!("{}{}", name, FILE_SUFFIX);
+
+        match (self.vars.get(name), self.vars.get(&file_name)) {
+            (Some(_), Some(_)) => bail!("Both {} and {} are set. Only use one of them.", name, file_name),
+            (Some(value), None) => Ok(Some(value.clone())),
+            (None, Some(path)) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {} from {}", name, path))?;
+                Ok(Some(content.trim_end_matches(['\r', '\n']).to_string()))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Resolve placeholders and overrides in the config. The prefix is used for the override variables, e.g. `RENDERER`.
+pub fn resolve(config: &mut Value, prefix: &str, environment: &Environment) -> Result<()> {
+    resolve_placeholders(config, environment)?;
+    apply_overrides(config, prefix, environment)
+}
+
+/// Replace the `${NAME}` placeholders in all strings of the config
+pub fn resolve_placeholders(config: &mut Value, environment: &Environment) -> Result<()> {
+    resolve_placeholders_at(config, "", environment)
+}
+
+/// Replace the `${NAME}` placeholders in the value at the path, e.g. `renderer.webdav_url`. A missing value is skipped.
+pub fn resolve_placeholders_in(config: &mut Value, path: &str, environment: &Environment) -> Result<()> {
+    match path.split('.').try_fold(config, |value, key| value.get_mut(key)) {
+        Some(value) => resolve_placeholders_at(value, path, environment),
+        None => Ok(()),
+    }
+}
+
+/// Names of all variables that can change the config, so that build scripts can rerun when they change
+pub fn variable_names(config: &Value, prefix: &str) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    collect_variable_names(config, "", prefix, &mut names);
+    names.into_iter().flat_map(|name| [format!("{}{}", name, FILE_SUFFIX), name]).collect()
+}
+
+fn resolve_placeholders_at(value: &mut Value, path: &str, environment: &Environment) -> Result<()> {
+    match value {
+        Value::String(text) => {
+            *text = expand(text, environment).with_context(|| format!("Failed to resolve {}", path))?;
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                resolve_placeholders_at(item, &format!("{}[{}]", path, i), environment)?;
+            }
+        }
+        Value::Object(object) => {
+            for (key, item) in object.iter_mut() {
+                resolve_placeholders_at(item, &join_path(path, key), environment)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace all `${NAME}` placeholders in the text
+fn expand(text: &str, environment: &Environment) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(placeholder) = rest.strip_prefix("${") else {
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let end = placeholder.find('}').with_context(|| format!("Unterminated placeholder in \"{}\"", text))?;
+        let (name, default) = match placeholder[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&placeholder[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("Invalid variable name \"{}\" in \"{}\"", name, text);
+        }
+
+        let value = match (environment.get(name)?, default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_string(),
+            (None, None) => bail!("Environment variable {} is not set", name),
+        };
+        result.push_str(&value);
+        rest = &placeholder[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Set the fields that have a `<PREFIX>__<PATH>` variable
+pub fn apply_overrides(config: &mut Value, prefix: &str, environment: &Environment) -> Result<()> {
+    let var_prefix = format!("{}{}", prefix, SEPARATOR);
+
+    // Both NAME and NAME_FILE refer to the same field
+    let names: BTreeSet<&str> = environment.vars.keys()
+        .filter(|name| name.starts_with(&var_prefix))
+        .map(|name| name.strip_suffix(FILE_SUFFIX).unwrap_or(name))
+        .collect();
+
+    for name in names {
+        let Some(text) = environment.get(name)? else {
+            continue;
+        };
+        let keys: Vec<String> = name[var_prefix.len()..].split(SEPARATOR).map(|key| key.to_lowercase()).collect();
+        set_value(config, &keys, text).with_context(|| format!("Failed to apply {}", name))?;
+    }
+
+    Ok(())
+}
+
+/// Set the field at the path and create missing objects on the way.
+/// Fields that are numbers or booleans in the config are parsed as JSON, everything else is a string.
+fn set_value(config: &mut Value, keys: &[String], text: String) -> Result<()> {
+    let mut value = config;
+    for key in keys {
+        if value.is_null() {
+            *value = Value::Object(Default::default());
+        }
+        let object = value.as_object_mut().with_context(|| format!("Can't set \"{}\" on a non-object value", key))?;
+        value = object.entry(key.as_str()).or_insert(Value::Null);
+    }
+
+    *value = match value {
+        Value::Number(_) | Value::Bool(_) => serde_json::from_str(&text).with_context(|| format!("Invalid value \"{}\"", text))?,
+        _ => Value::String(text),
+    };
+    Ok(())
+}
+
+fn collect_variable_names(value: &Value, path: &str, prefix: &str, names: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, item) in object {
+                collect_variable_names(item, &join_path(path, key), prefix, names);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_variable_names(item, path, prefix, names);
+            }
+        }
+        Value::String(text) => {
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find('$') {
+                rest = &rest[start..];
+                if let Some(escaped) = rest.strip_prefix("$${") {
+                    rest = escaped;
+                } else if let Some((name, _)) = rest.strip_prefix("${").and_then(|p| p.split_once('}')) {
+                    names.insert(name.split(":-").next().unwrap_or_default().to_string());
+                    rest = &rest[2 + name.len() + 1..];
+                } else {
+                    rest = &rest[1..];
+                }
+            }
+            names.insert(override_name(prefix, path));
+        }
+        _ => {
+            names.insert(override_name(prefix, path));
+        }
+    }
+}
+
+fn override_name(prefix: &str, path: &str) -> String {
+    format!("{}{}{}", prefix, SEPARATOR, path.replace('.', SEPARATOR).to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use serde_json::json;
+    use super::*;
+
+    fn example_config() -> Value {
+        json!({
+            "wifi": { "ssid": "Home", "psk": "${WIFI_PSK}" },
+            "url": "http://${HOST:-localhost}/$${path}",
+            "location": { "lat": 52.5 }
+        })
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let environment = Environment::from_vars([("WIFI_PSK", "secret")]);
+        let mut config = example_config();
+        resolve(&mut config, "ESP", &environment).unwrap();
+
+        assert_eq!(config["wifi"]["psk"], "secret");
+        assert_eq!(config["url"], "http://localhost/${path}");
+    }
+
+    #[test]
+    fn test_missing_placeholder() {
+        let error = resolve(&mut example_config(), "ESP", &Environment::default()).unwrap_err();
+        assert_eq!(format!("{:#}", error), "Failed to resolve wifi.psk: Environment variable WIFI_PSK is not set");
+    }
+
+    #[test]
+    fn test_secret_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "from file").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let environment = Environment::from_vars([("WIFI_PSK_FILE", path)]);
+        let mut config = example_config();
+        resolve(&mut config, "ESP", &environment).unwrap();
+        assert_eq!(config["wifi"]["psk"], "from file");
+
+        let environment = Environment::from_vars([("WIFI_PSK", "secret"), ("WIFI_PSK_FILE", path)]);
+        assert!(resolve(&mut example_config(), "ESP", &environment).is_err());
+    }
+
+    #[test]
+    fn test_override_order() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "override from file").unwrap();
+
+        let environment = Environment::from_vars([
+            ("WIFI_PSK", "placeholder"),
+            ("HOST", "example.com"),
+            ("ESP__WIFI__PSK", "override"),
+            ("ESP__WIFI__SSID_FILE", file.path().to_str().unwrap()),
+            ("ESP__LOCATION__LAT", "48.1"),
+            ("ESP__NEW__KEY", "12"),
+            ("OTHER__URL", "ignored"),
+        ]);
+        let mut config = example_config();
+        resolve(&mut config, "ESP", &environment).unwrap();
+
+        // Overrides win over placeholders, which win over the file
+        assert_eq!(config["wifi"]["psk"], "override");
+        assert_eq!(config["wifi"]["ssid"], "override from file");
+        assert_eq!(config["url"], "http://example.com/${path}");
+        // Types of existing fields are kept
+        assert_eq!(config["location"]["lat"], 48.1);
+        assert_eq!(config["new"]["key"], "12");
+    }
+
+    #[test]
+    fn test_variable_names() {
+        let names = variable_names(&example_config(), "ESP");
+        for name in ["WIFI_PSK", "WIFI_PSK_FILE", "HOST", "ESP__WIFI__SSID", "ESP__WIFI__PSK_FILE", "ESP__LOCATION__LAT"] {
+            assert!(names.contains(&name.to_string()), "{} is missing", name);
+        }
+        assert!(!names.contains(&"path".to_string()));
+    }
+}
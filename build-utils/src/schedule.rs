@@ -0,0 +1,335 @@
+//! When new images are rendered by the renderer and fetched by the ESP.
+//!
+//! ```json
+//! "schedule": {
+//!     "cron": "0 * * * *",
+//!     "quiet_hours": { "start": "23:00", "end": "06:00" },
+//!     "weekdays": {
+//!         "saturday": { "quiet_hours": { "start": "00:00", "end": "08:00" } },
+//!         "sunday": { "times": ["09:00", "12:00", "18:00"] }
+//!     }
+//! }
+//! ```
+//!
+//! A rule sets the refresh times with one of `cron` (minute, hour and weekday fields, day of month and month must be `*`),
+//! `interval` (minutes since midnight) or `times` (list of `HH:MM`) and skips the `quiet_hours`.
+//! Rules in `weekdays` override the fields of the base rule on that day.
+//!
+//! The schedule is expanded to a sorted list of minutes since Monday 00:00 in local time, which is easy to export to the ESP.
+
+use std::collections::BTreeSet;
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Timelike};
+use serde_json::{Map, Value};
+
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+pub const MINUTES_PER_WEEK: u16 = 7 * MINUTES_PER_DAY;
+
+pub const WEEKDAYS: &[&str] = &["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+const TRIGGER_KEYS: &[&str] = &["cron", "interval", "times"];
+const RULE_KEYS: &[&str] = &["cron", "interval", "times", "quiet_hours"];
+
+/// Refresh times as minutes since Monday 00:00 in local time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: Vec<u16>,
+}
+
+impl Default for Schedule {
+    /// Every full hour
+    fn default() -> Self {
+        Schedule { minutes: (0..MINUTES_PER_WEEK).step_by(60).collect() }
+    }
+}
+
+impl Schedule {
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let object = value.as_object().context("The schedule must be an object")?;
+        check_keys(object, &[RULE_KEYS, &["weekdays"]].concat())?;
+
+        let overrides = match object.get("weekdays") {
+            Some(weekdays) => weekdays.as_object().context("weekdays must be an object")?.clone(),
+            None => Map::new(),
+        };
+        check_keys(&overrides, WEEKDAYS)?;
+
+        let mut minutes = Vec::new();
+        for (day, name) in WEEKDAYS.iter().enumerate() {
+            let rule = match overrides.get(*name) {
+                Some(day_rule) => {
+                    let day_rule = day_rule.as_object().with_context(|| format!("weekdays.{} must be an object", name))?;
+                    check_keys(day_rule, RULE_KEYS).with_context(|| format!("Invalid schedule on {}", name))?;
+                    merge_rules(object, day_rule)
+                }
+                None => object.clone(),
+            };
+            let day_minutes = expand_rule(&rule, day as u8).with_context(|| format!("Invalid schedule on {}", name))?;
+            minutes.extend(day_minutes.into_iter().map(|m| day as u16 * MINUTES_PER_DAY + m));
+        }
+
+        ensure!(!minutes.is_empty(), "The schedule never refreshes");
+        Ok(Schedule { minutes })
+    }
+
+    /// Sorted minutes since Monday 00:00 in local time
+    pub fn minutes_of_week(&self) -> &[u16] {
+        &self.minutes
+    }
+
+    /// The first refresh time after the given time
+    pub fn next_after<Tz: TimeZone>(&self, date_time: &DateTime<Tz>) -> DateTime<Tz> {
+        let local = date_time.naive_local();
+        let week_start = (local.date() - Duration::days(local.weekday().num_days_from_monday() as i64))
+            .and_time(NaiveTime::MIN);
+
+        // A local time that repeats when the clocks go back is both before and after the given time,
+        // so the local times are only a rough order. A day covers any change of the UTC offset.
+        let mut refreshes = (-1..3)
+            .flat_map(|week| self.minutes.iter().map(move |&m| week_start + Duration::weeks(week) + Duration::minutes(m as i64)))
+            .filter(|candidate| *candidate > local - Duration::days(1))
+            .filter_map(|candidate| {
+                // Skips the local times in DST gaps, which don't exist
+                let mapped = date_time.timezone().from_local_datetime(&candidate);
+                [mapped.clone().earliest(), mapped.latest()].into_iter().flatten()
+                    .find(|refresh| refresh > date_time)
+                    .map(|refresh| (candidate, refresh))
+            });
+        let (first_candidate, first) = refreshes.next().expect("A schedule has at least one refresh per week");
+        refreshes
+            .take_while(|(candidate, _)| *candidate <= first_candidate + Duration::days(1))
+            .map(|(_, refresh)| refresh)
+            .fold(first, |earliest, refresh| earliest.min(refresh))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Schedule {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Schedule::from_value(&value).map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+/// Fields of the weekday rule replace the ones of the base rule. A new trigger replaces the old one.
+fn merge_rules(base: &Map<String, Value>, day_rule: &Map<String, Value>) -> Map<String, Value> {
+    let mut rule = base.clone();
+    rule.remove("weekdays");
+    if TRIGGER_KEYS.iter().any(|key| day_rule.contains_key(*key)) {
+        for key in TRIGGER_KEYS {
+            rule.remove(*key);
+        }
+    }
+    rule.extend(day_rule.clone());
+    rule
+}
+
+/// Minutes of the day the rule refreshes at
+fn expand_rule(rule: &Map<String, Value>, weekday: u8) -> Result<BTreeSet<u16>> {
+    let triggers: Vec<&str> = TRIGGER_KEYS.iter().copied().filter(|key| rule.contains_key(*key)).collect();
+    let mut minutes = match triggers.as_slice() {
+        [] => bail!("Set one of: {}", TRIGGER_KEYS.join(", ")),
+        ["cron"] => {
+            let expression = rule["cron"].as_str().context("cron must be a string")?;
+            cron_minutes(expression, weekday).with_context(|| format!("Invalid cron expression \"{}\"", expression))?
+        }
+        ["interval"] => {
+            let interval = rule["interval"].as_u64().context("interval must be a whole number of minutes")?;
+            ensure!((1..=MINUTES_PER_DAY as u64).contains(&interval), "interval must be between 1 and {} minutes", MINUTES_PER_DAY);
+            (0..MINUTES_PER_DAY).step_by(interval as usize).collect()
+        }
+        ["times"] => {
+            let times = rule["times"].as_array().context("times must be a list like [\"06:00\", \"18:00\"]")?;
+            times.iter()
+                .map(|time| parse_time(time.as_str().context("times must be strings like \"06:00\"")?))
+                .collect::<Result<_>>()?
+        }
+        _ => bail!("Only use one of: {}", TRIGGER_KEYS.join(", ")),
+    };
+
+    if let Some(quiet_hours) = rule.get("quiet_hours").filter(|q| !q.is_null()) {
+        let start = parse_time(quiet_hours.get("start").and_then(Value::as_str).context("quiet_hours needs a start like \"23:00\"")?)?;
+        let end = parse_time(quiet_hours.get("end").and_then(Value::as_str).context("quiet_hours needs an end like \"06:00\"")?)?;
+        // Quiet hours can span midnight, e.g. from 23:00 to 06:00
+        minutes.retain(|&m| if start <= end { m < start || m >= end } else { m < start && m >= end });
+    }
+
+    Ok(minutes)
+}
+
+/// Minutes of the day that match the cron expression on the weekday (0 is Monday)
+fn cron_minutes(expression: &str, weekday: u8) -> Result<BTreeSet<u16>> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let [minute, hour, day_of_month, month, day_of_week] = fields.as_slice() else {
+        bail!("Expected 5 fields: minute hour day-of-month month day-of-week");
+    };
+    ensure!(*day_of_month == "*" && *month == "*", "Day of month and month must be *, because the schedule repeats every week");
+
+    let minutes = parse_cron_field(minute, 0, 59)?;
+    let hours = parse_cron_field(hour, 0, 23)?;
+
+    // Cron counts weekdays from Sunday as 0 or 7
+    let cron_weekday = (weekday + 1) % 7;
+    let weekdays = parse_cron_field(day_of_week, 0, 7)?;
+    let matches_weekday = weekdays.contains(&cron_weekday) || (cron_weekday == 0 && weekdays.contains(&7));
+    if !matches_weekday {
+        return Ok(BTreeSet::new());
+    }
+
+    Ok(hours.iter().flat_map(|&h| minutes.iter().map(move |&m| h as u16 * 60 + m as u16)).collect())
+}
+
+/// Parse a field like `*`, `*/15`, `6-22`, `8-20/2` or `0,30`
+fn parse_cron_field(field: &str, min: u8, max: u8) -> Result<BTreeSet<u8>> {
+    let mut values = BTreeSet::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u8>().ok().filter(|s| *s > 0).with_context(|| format!("Invalid step in \"{}\"", part))?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (parse_cron_value(start)?, parse_cron_value(end)?),
+                None => {
+                    let value = parse_cron_value(range)?;
+                    // `5/10` means from 5 to the end
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        ensure!(min <= start && start <= end && end <= max, "\"{}\" is out of range {}-{}", part, min, max);
+        values.extend((start..=end).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+fn parse_cron_value(text: &str) -> Result<u8> {
+    const NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+    if let Some(index) = NAMES.iter().position(|name| name.eq_ignore_ascii_case(text)) {
+        return Ok(index as u8);
+    }
+    text.parse().with_context(|| format!("Invalid value \"{}\"", text))
+}
+
+/// Minutes since midnight of a time like `06:30`
+fn parse_time(text: &str) -> Result<u16> {
+    let time = NaiveTime::parse_from_str(text, "%H:%M").with_context(|| format!("Invalid time \"{}\", use HH:MM", text))?;
+    Ok(time.hour() as u16 * 60 + time.minute() as u16)
+}
+
+fn check_keys(object: &Map<String, Value>, known_keys: &[&str]) -> Result<()> {
+    match object.keys().find(|key| !known_keys.contains(&key.as_str())) {
+        Some(key) => bail!("Unknown field \"{}\". Known fields are: {}", key, known_keys.join(", ")),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+    use serde_json::json;
+    use super::*;
+
+    fn minute(day: u16, hour: u16, minute: u16) -> u16 {
+        day * MINUTES_PER_DAY + hour * 60 + minute
+    }
+
+    #[test]
+    fn test_cron() {
+        let schedule = Schedule::from_value(&json!({ "cron": "0,30 6-8 * * mon-fri" })).unwrap();
+        let minutes = schedule.minutes_of_week();
+        assert_eq!(minutes.len(), 5 * 6);
+        assert_eq!(minutes[0], minute(0, 6, 0));
+        assert_eq!(minutes[5], minute(0, 8, 30));
+        assert_eq!(*minutes.last().unwrap(), minute(4, 8, 30));
+
+        // Sunday as 0 and 7
+        let sunday = Schedule::from_value(&json!({ "cron": "0 12 * * 0" })).unwrap();
+        assert_eq!(sunday.minutes_of_week(), &[minute(6, 12, 0)]);
+        assert_eq!(Schedule::from_value(&json!({ "cron": "0 12 * * 7" })).unwrap(), sunday);
+
+        assert!(Schedule::from_value(&json!({ "cron": "0 12 1 * *" })).is_err());
+        assert!(Schedule::from_value(&json!({ "cron": "0 25 * * *" })).is_err());
+        assert!(Schedule::from_value(&json!({ "cron": "*/0 * * * *" })).is_err());
+    }
+
+    #[test]
+    fn test_quiet_hours_and_weekdays() {
+        let schedule = Schedule::from_value(&json!({
+            "interval": 60,
+            "quiet_hours": { "start": "23:00", "end": "06:00" },
+            "weekdays": {
+                "saturday": { "quiet_hours": { "start": "00:00", "end": "08:00" } },
+                "sunday": { "times": ["09:00", "18:30"], "quiet_hours": null }
+            }
+        })).unwrap();
+        let minutes = schedule.minutes_of_week();
+
+        // 06:00 to 22:00 from Monday to Friday, 08:00 to 23:00 on Saturday
+        assert_eq!(minutes.len(), 5 * 17 + 16 + 2);
+        assert_eq!(minutes[0], minute(0, 6, 0));
+        assert!(!minutes.contains(&minute(0, 23, 0)));
+        assert!(minutes.contains(&minute(5, 23, 0)));
+        assert!(!minutes.contains(&minute(5, 7, 0)));
+        assert_eq!(&minutes[minutes.len() - 2..], &[minute(6, 9, 0), minute(6, 18, 30)]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Schedule::from_value(&json!({})).is_err());
+        assert!(Schedule::from_value(&json!({ "cron": "0 * * * *", "interval": 30 })).is_err());
+        assert!(Schedule::from_value(&json!({ "times": ["25:00"] })).is_err());
+        assert!(Schedule::from_value(&json!({ "times": [] })).is_err());
+        assert!(Schedule::from_value(&json!({ "interval": 60, "weekdays": { "caturday": {} } })).is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        let schedule = Schedule::from_value(&json!({ "times": ["06:00", "18:00"], "weekdays": { "sunday": { "times": ["12:00"] } } })).unwrap();
+
+        // Tuesday
+        let now = Berlin.with_ymd_and_hms(2024, 12, 24, 8, 0, 0).unwrap();
+        assert_eq!(schedule.next_after(&now), Berlin.with_ymd_and_hms(2024, 12, 24, 18, 0, 0).unwrap());
+
+        // Exactly at a refresh time, the next one is used
+        let now = Berlin.with_ymd_and_hms(2024, 12, 24, 18, 0, 0).unwrap();
+        assert_eq!(schedule.next_after(&now), Berlin.with_ymd_and_hms(2024, 12, 25, 6, 0, 0).unwrap());
+
+        // Sunday evening to Monday morning of the next week
+        let now = Berlin.with_ymd_and_hms(2024, 12, 29, 13, 0, 0).unwrap();
+        assert_eq!(schedule.next_after(&now), Berlin.with_ymd_and_hms(2024, 12, 30, 6, 0, 0).unwrap());
+
+        assert_eq!(Schedule::default().next_after(&now), Berlin.with_ymd_and_hms(2024, 12, 29, 14, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_next_after_dst_gap() {
+        let schedule = Schedule::from_value(&json!({ "times": ["02:30", "04:00"] })).unwrap();
+        // 02:30 doesn't exist on the last Sunday of March in Berlin
+        let now = Berlin.with_ymd_and_hms(2024, 3, 31, 1, 0, 0).unwrap();
+        assert_eq!(schedule.next_after(&now), Berlin.with_ymd_and_hms(2024, 3, 31, 4, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_next_after_dst_fall_back() {
+        // 02:00 to 03:00 happens twice on the last Sunday of October in Berlin
+        let schedule = Schedule::from_value(&json!({ "times": ["02:15", "02:45", "04:00"] })).unwrap();
+        let local = |hour, minute| Berlin.with_ymd_and_hms(2024, 10, 27, hour, minute, 0);
+        let summer = |hour, minute| local(hour, minute).earliest().unwrap();
+        let winter = |hour, minute| local(hour, minute).latest().unwrap();
+
+        assert_eq!(schedule.next_after(&summer(1, 0)), summer(2, 15));
+        assert_eq!(schedule.next_after(&summer(2, 30)), summer(2, 45));
+        // The refreshes repeat in winter time
+        assert_eq!(schedule.next_after(&summer(2, 50)), winter(2, 15));
+        assert_eq!(schedule.next_after(&winter(2, 30)), winter(2, 45));
+        assert_eq!(schedule.next_after(&winter(2, 50)), winter(4, 0));
+
+        // Never before the given time, which the renderer relies on to sleep until the next refresh
+        let mut now = summer(1, 0);
+        for _ in 0..10 {
+            let next = schedule.next_after(&now);
+            assert!(next > now);
+            now = next;
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! One config for both packages in the workspace root:
+//!
+//! ```json
+//! {
+//!     "timezone": "Europe/Berlin",
+//!     "renderer": { "webdav_url": "http://192.168.0.2/webdav/", "image_name": "esp_image.img", ... },
+//!     "esp": { "wifi": { "ssid": "Wifi", "psk": "${WIFI_PSK}" } }
+//! }
+//! ```
+//!
+//! Top-level values like the timezone and the schedule are used by both sections unless a section sets its own.
+//! The ESP's `image_url` is derived from where the renderer puts the image if it isn't set.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use url::Url;
+
+use crate::overrides::{self, Environment};
+use crate::validate::{join_path, Validator};
+
+pub const RENDERER: &str = "renderer";
+pub const ESP: &str = "esp";
+
+/// Top-level keys that are shared by both sections
+const SHARED_KEYS: &[&str] = &["timezone", "schedule"];
+/// Values of the renderer that the ESP's `image_url` is derived from
+const IMAGE_URL_KEYS: &[&str] = &["webdav_url", "image_name", "server_address"];
+
+/// The config of one package taken from the shared config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub config: Value,
+    /// Problems that don't prevent building, e.g. different timezones
+    pub warnings: Vec<String>,
+}
+
+/// Whether the config is a shared config instead of the config of a single package
+pub fn is_shared(config: &Value) -> bool {
+    config.get(RENDERER).is_some_and(Value::is_object) || config.get(ESP).is_some_and(Value::is_object)
+}
+
+/// Replace the `${NAME}` placeholders the section depends on: its own values, the shared top-level values and,
+/// for the ESP, the renderer values its `image_url` is derived from.
+/// The other section is left alone, so that building one package doesn't need the secrets of the other one.
+pub fn resolve_placeholders(shared: &mut Value, name: &str, environment: &Environment) -> Result<()> {
+    let mut paths: Vec<String> = SHARED_KEYS.iter().chain([&name]).map(|key| key.to_string()).collect();
+    if name == ESP {
+        paths.extend(IMAGE_URL_KEYS.iter().map(|key| join_path(RENDERER, key)));
+    }
+
+    for path in paths {
+        overrides::resolve_placeholders_in(shared, &path, environment)?;
+    }
+    Ok(())
+}
+
+/// Get the config of the section ([`RENDERER`] or [`ESP`]) with the shared values filled in
+pub fn section(shared: &Value, name: &str) -> Result<Section> {
+    let mut v = Validator::new(shared);
+    v.object("", true, &[SHARED_KEYS, &[RENDERER, ESP]].concat());
+    v.timezone("timezone", false);
+    // The keys of the section are checked by the package itself
+    if v.field(name, true).is_some_and(|section| !section.is_object()) {
+        v.issue(name, "Expected an object", None);
+    }
+    v.finish()?;
+
+    let mut config = with_shared_values(shared, name)?;
+    let mut warnings = Vec::new();
+
+    let renderer = with_shared_values(shared, RENDERER).ok();
+    let esp = with_shared_values(shared, ESP).ok();
+    if let (Some(renderer), Some(esp)) = (&renderer, &esp) {
+        if let (Some(renderer_tz), Some(esp_tz)) = (renderer["timezone"].as_str(), esp["timezone"].as_str()) {
+            if renderer_tz != esp_tz {
+                warnings.push(format!(
+                    "The timezone of the renderer ({}) and the ESP ({}) differ. Set it once at the top level of the config instead.",
+                    renderer_tz, esp_tz
+                ));
+            }
+        }
+    }
+
+    if name == ESP {
+        let derived = renderer.as_ref().map(image_url).transpose()?.flatten();
+        match (config.get("image_url").and_then(Value::as_str), derived) {
+            (None, Some(derived)) => {
+                config["image_url"] = Value::String(derived);
+            }
+            (Some(image_url), Some(derived)) if image_url != derived => warnings.push(format!(
+                "esp.image_url ({}) doesn't match where the renderer puts the image ({})",
+                image_url, derived
+            )),
+            (None, None) => bail!("Can't derive esp.image_url from the renderer config. Set esp.image_url or renderer.webdav_url."),
+            _ => {}
+        }
+    }
+
+    Ok(Section { config, warnings })
+}
+
+/// URL the ESP can download the image from, taken from the WebDAV upload target or the address of the built-in server
+pub fn image_url(renderer: &Value) -> Result<Option<String>> {
+    let Some(image_name) = renderer.get("image_name").and_then(Value::as_str) else {
+        return Ok(None);
+    };
+
+    if let Some(webdav_url) = renderer.get("webdav_url").and_then(Value::as_str) {
+        let mut url = Url::parse(webdav_url).context("Invalid renderer.webdav_url")?;
+        // Same as the renderer's WebDAV client, the URL is a directory
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        return Ok(Some(url.join(image_name)?.to_string()));
+    }
+
+    // A server listening on all interfaces doesn't tell which address the ESP should use
+    let server_address = renderer.get("server_address").and_then(Value::as_str);
+    match server_address.and_then(|address| address.rsplit_once(':')) {
+        Some((host, port)) if !["0.0.0.0", "[::]", "::"].contains(&host) => {
+            Ok(Some(format!("http://{}:{}/{}", host, port, image_name)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn with_shared_values(shared: &Value, name: &str) -> Result<Value> {
+    let mut config = shared.get(name)
+        .and_then(Value::as_object)
+        .with_context(|| format!("The config has no \"{}\" section", name))?
+        .clone();
+
+    for key in SHARED_KEYS {
+        if let Some(value) = shared.get(key) {
+            config.entry(*key).or_insert_with(|| value.clone());
+        }
+    }
+
+    Ok(Value::Object(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn shared() -> Value {
+        json!({
+            "timezone": "Europe/Berlin",
+            "renderer": { "webdav_url": "http://192.168.0.2/webdav", "image_name": "esp_image.img" },
+            "esp": { "wifi": { "ssid": "Wifi", "psk": "secret" } }
+        })
+    }
+
+    #[test]
+    fn test_sections() {
+        let renderer = section(&shared(), RENDERER).unwrap();
+        assert_eq!(renderer.config["timezone"], "Europe/Berlin");
+        assert_eq!(renderer.config["image_name"], "esp_image.img");
+        assert!(renderer.config.get("wifi").is_none());
+
+        let esp = section(&shared(), ESP).unwrap();
+        assert_eq!(esp.config["timezone"], "Europe/Berlin");
+        assert_eq!(esp.config["image_url"], "http://192.168.0.2/webdav/esp_image.img");
+        assert!(esp.warnings.is_empty());
+
+        assert!(is_shared(&shared()));
+        assert!(!is_shared(&renderer.config));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut shared = shared();
+        shared["esp"]["timezone"] = "Europe/London".into();
+        shared["esp"]["image_url"] = "http://192.168.0.2/other.img".into();
+
+        let esp = section(&shared, ESP).unwrap();
+        assert_eq!(esp.config["timezone"], "Europe/London");
+        assert_eq!(esp.warnings.len(), 2);
+        assert!(esp.warnings[0].contains("Europe/Berlin"));
+    }
+
+    #[test]
+    fn test_example_config() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../example-config.jsonc");
+        let mut shared = crate::format::read(&path).unwrap();
+        let environment = Environment::from_vars([("WIFI_PSK", "secret"), ("WEBDAV_PASSWORD", "secret")]);
+        resolve_placeholders(&mut shared, ESP, &environment).unwrap();
+
+        let esp = section(&shared, ESP).unwrap();
+        assert_eq!(esp.config["image_url"], "http://123.123.123.123/webdav/esp_image.img");
+        assert_eq!(esp.config["wifi"]["psk"], "secret");
+        assert!(esp.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_placeholders_of_other_section() {
+        let mut shared = shared();
+        shared["timezone"] = "${TZ}".into();
+        shared["renderer"]["webdav_url"] = "http://${WEBDAV_HOST}/webdav".into();
+        shared["renderer"]["webdav_auth"] = json!({ "username": "user", "password": "${WEBDAV_PASSWORD}" });
+        shared["esp"]["wifi"]["psk"] = "${WIFI_PSK}".into();
+
+        // The ESP needs the renderer's address for its image_url, but not its password
+        let mut esp = shared.clone();
+        let environment = Environment::from_vars([("TZ", "Europe/Berlin"), ("WEBDAV_HOST", "192.168.0.2"), ("WIFI_PSK", "secret")]);
+        resolve_placeholders(&mut esp, ESP, &environment).unwrap();
+        let esp = section(&esp, ESP).unwrap().config;
+        assert_eq!(esp["image_url"], "http://192.168.0.2/webdav/esp_image.img");
+        assert_eq!(esp["wifi"]["psk"], "secret");
+        assert_eq!(esp["timezone"], "Europe/Berlin");
+
+        // The renderer doesn't need the Wifi password
+        let mut renderer = shared.clone();
+        let environment = Environment::from_vars([("TZ", "Europe/Berlin"), ("WEBDAV_HOST", "192.168.0.2"), ("WEBDAV_PASSWORD", "secret")]);
+        resolve_placeholders(&mut renderer, RENDERER, &environment).unwrap();
+        assert_eq!(section(&renderer, RENDERER).unwrap().config["webdav_auth"]["password"], "secret");
+
+        let error = resolve_placeholders(&mut shared.clone(), ESP, &Environment::default()).unwrap_err();
+        assert!(format!("{:#}", error).contains("timezone"));
+    }
+
+    #[test]
+    fn test_image_url_from_server() {
+        assert_eq!(
+            image_url(&json!({ "image_name": "a.img", "server_address": "192.168.0.3:8080" })).unwrap(),
+            Some("http://192.168.0.3:8080/a.img".to_string())
+        );
+        assert_eq!(image_url(&json!({ "image_name": "a.img", "server_address": "0.0.0.0:8080" })).unwrap(), None);
+
+        let mut shared = shared();
+        shared["renderer"] = json!({ "image_name": "a.img", "server_address": "0.0.0.0:8080" });
+        assert!(section(&shared, ESP).is_err());
+    }
+}
@@ -0,0 +1,551 @@
+use std::fmt;
+use std::str::FromStr;
+use chrono::format::{Item, StrftimeItems};
+use serde::de::value::MapDeserializer;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde_json::Value;
+
+const STRFTIME_DOCS: &str = "https://docs.rs/chrono/latest/chrono/format/strftime/index.html";
+
+/// A single problem found in the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    /// Dotted path of the field, e.g. `weekday_names.monday`
+    pub path: String,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n    hint: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// All problems found in the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError(pub Vec<Issue>);
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.0.len();
+        write!(f, "Config has {} problem{}:", count, if count == 1 { "" } else { "s" })?;
+        for issue in &self.0 {
+            write!(f, "\n  - {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks a config value against the expected structure and collects every problem instead of stopping at the first one.
+///
+/// Paths are dotted like in [`crate::get_str`], e.g. `weather_conditions.rain`. The empty path is the root object.
+pub struct Validator<'a> {
+    root: &'a Value,
+    issues: Vec<Issue>,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(root: &'a Value) -> Self {
+        Validator { root, issues: Vec::new() }
+    }
+
+    /// Report a problem
+    pub fn issue(&mut self, path: &str, message: impl Into<String>, suggestion: Option<String>) {
+        self.issues.push(Issue {
+            path: display_path(path),
+            message: message.into(),
+            suggestion,
+        });
+    }
+
+    /// Get a field and report it if it is required but missing
+    pub fn field(&mut self, path: &str, required: bool) -> Option<&'a Value> {
+        let value = lookup(self.root, path);
+        if value.is_none() && required {
+            let (parent, key) = path.rsplit_once('.').unwrap_or(("", path));
+            self.issue(path, "Missing field", Some(format!("Add \"{}\" to {}", key, display_path(parent))));
+        }
+        value
+    }
+
+    /// Check that the field is an object and report unknown keys.
+    /// Returns whether the object exists.
+    pub fn object(&mut self, path: &str, required: bool, known_keys: &[&str]) -> bool {
+        let Some(value) = self.field(path, required) else {
+            return false;
+        };
+        let Some(object) = value.as_object() else {
+            self.type_issue(path, "an object", value);
+            return false;
+        };
+
+        for key in object.keys() {
+            if known_keys.contains(&key.as_str()) {
+                continue;
+            }
+            let suggestion = match closest(key, known_keys.iter().copied()) {
+                Some(known) => format!("Did you mean \"{}\"?", known),
+                None => format!("Remove it. Known fields are: {}", known_keys.join(", ")),
+            };
+            self.issue(&join_path(path, key), "Unknown field", Some(suggestion));
+        }
+
+        true
+    }
+
+    pub fn string(&mut self, path: &str, required: bool) -> Option<&'a str> {
+        let value = self.field(path, required)?;
+        let text = value.as_str();
+        if text.is_none() {
+            self.type_issue(path, "a string", value);
+        }
+        text
+    }
+
+    pub fn number(&mut self, path: &str, required: bool) -> Option<f64> {
+        let value = self.field(path, required)?;
+        let number = value.as_f64();
+        if number.is_none() {
+            self.type_issue(path, "a number", value);
+        }
+        number
+    }
+
+    /// Check that the field is a whole number in the inclusive range, as integer fields need it
+    pub fn integer_in(&mut self, path: &str, required: bool, min: i64, max: i64) -> Option<i64> {
+        let value = self.field(path, required)?;
+        let Some(number) = value.as_i64() else {
+            match value.as_f64() {
+                Some(number) => self.issue(path, format!("{} isn't a whole number", number), Some(format!("Use a whole number between {} and {}", min, max))),
+                None => self.type_issue(path, "a whole number", value),
+            }
+            return None;
+        };
+        if number < min || number > max {
+            self.issue(path, format!("{} is out of range", number), Some(format!("Use a value between {} and {}", min, max)));
+            return None;
+        }
+        Some(number)
+    }
+
+    /// Check that the field is a number in the inclusive range
+    pub fn number_in(&mut self, path: &str, required: bool, min: f64, max: f64) -> Option<f64> {
+        let number = self.number(path, required)?;
+        if number < min || number > max {
+            self.issue(path, format!("{} is out of range", number), Some(format!("Use a value between {} and {}", min, max)));
+            return None;
+        }
+        Some(number)
+    }
+
+    pub fn boolean(&mut self, path: &str, required: bool) -> Option<bool> {
+        let value = self.field(path, required)?;
+        let boolean = value.as_bool();
+        if boolean.is_none() {
+            self.type_issue(path, "true or false", value);
+        }
+        boolean
+    }
+
+    /// Check that the field is one of the given strings
+    pub fn one_of(&mut self, path: &str, required: bool, choices: &[&str]) -> Option<&'a str> {
+        let text = self.string(path, required)?;
+        if choices.contains(&text) {
+            return Some(text);
+        }
+        self.unknown_value(path, text, choices);
+        None
+    }
+
+    /// Check that the field is a list of strings. Items that aren't strings are reported with their index like `tags[1]`.
+    pub fn strings(&mut self, path: &str, required: bool) -> Option<Vec<&'a str>> {
+        let value = self.field(path, required)?;
+        let Some(items) = value.as_array() else {
+            self.type_issue(path, "a list", value);
+            return None;
+        };
+
+        let mut strings = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            match item.as_str() {
+                Some(text) => strings.push(text),
+                None => self.type_issue(&format!("{}[{}]", path, i), "a string", item),
+            }
+        }
+        Some(strings)
+    }
+
+    /// Check that the field is a list of the given strings
+    pub fn each_one_of(&mut self, path: &str, required: bool, choices: &[&str]) -> Option<Vec<&'a str>> {
+        let items = self.strings(path, required)?;
+        for (i, text) in items.iter().enumerate() {
+            if !choices.contains(text) {
+                self.unknown_value(&format!("{}[{}]", path, i), text, choices);
+            }
+        }
+        Some(items)
+    }
+
+    /// Check that the field is an IANA timezone name like `Europe/Berlin`
+    pub fn timezone(&mut self, path: &str, required: bool) -> Option<chrono_tz::Tz> {
+        let text = self.string(path, required)?;
+        match chrono_tz::Tz::from_str(text) {
+            Ok(tz) => Some(tz),
+            Err(_) => {
+                let suggestion = match closest(text, chrono_tz::TZ_VARIANTS.iter().map(|tz| tz.name())) {
+                    Some(name) => format!("Did you mean \"{}\"?", name),
+                    None => "Use a timezone name like \"Europe/Berlin\"".to_string(),
+                };
+                self.issue(path, format!("Unknown timezone \"{}\"", text), Some(suggestion));
+                None
+            }
+        }
+    }
+
+    /// Check that the field is a valid strftime format string
+    pub fn time_format(&mut self, path: &str, required: bool) -> Option<&'a str> {
+        let text = self.string(path, required)?;
+        if StrftimeItems::new(text).any(|item| item == Item::Error) {
+            self.issue(path, format!("Invalid time format \"{}\"", text), Some(format!("See {} for the supported specifiers", STRFTIME_DOCS)));
+            return None;
+        }
+        Some(text)
+    }
+
+    /// Check that the field is an absolute http(s) URL
+    pub fn url(&mut self, path: &str, required: bool) -> Option<url::Url> {
+        let text = self.string(path, required)?;
+        match url::Url::parse(text) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Some(url),
+            Ok(url) => {
+                self.issue(path, format!("Unsupported URL scheme \"{}\"", url.scheme()), Some("Use a http:// or https:// URL".to_string()));
+                None
+            }
+            Err(e) => {
+                self.issue(path, format!("Invalid URL \"{}\": {}", text, e), Some("Use a full URL like \"http://192.168.0.2/webdav/\"".to_string()));
+                None
+            }
+        }
+    }
+
+    /// Check that the field is a valid refresh schedule, see [`crate::schedule`]
+    pub fn schedule(&mut self, path: &str, required: bool) -> Option<crate::schedule::Schedule> {
+        let value = self.field(path, required)?;
+        match crate::schedule::Schedule::from_value(value) {
+            Ok(schedule) => Some(schedule),
+            Err(e) => {
+                self.issue(path, format!("{:#}", e), Some("Use one of cron, interval or times, e.g. { \"cron\": \"0 * * * *\" }".to_string()));
+                None
+            }
+        }
+    }
+
+    /// Return all collected problems as error
+    pub fn finish(self) -> Result<(), ValidationError> {
+        if self.issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError(self.issues))
+        }
+    }
+
+    fn unknown_value(&mut self, path: &str, text: &str, choices: &[&str]) {
+        let suggestion = match closest(text, choices.iter().copied()) {
+            Some(choice) => format!("Did you mean \"{}\"?", choice),
+            None => format!("Use one of: {}", choices.join(", ")),
+        };
+        self.issue(path, format!("Unknown value \"{}\"", text), Some(suggestion));
+    }
+
+    fn type_issue(&mut self, path: &str, expected: &str, found: &Value) {
+        let found = match found {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "an array",
+            Value::Object(_) => "an object",
+        };
+        self.issue(path, format!("Expected {}, found {}", expected, found), None);
+    }
+}
+
+/// Keys of a struct as serde reads them, aliases included.
+/// Use them for [`Validator::object`], so that the known keys can't get out of sync with the config types.
+///
+/// Panics if `T` isn't deserialized as a struct, e.g. because it's flattened or has a custom `Deserialize`.
+pub fn serde_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    match introspect::<T>() {
+        Some(Introspected::Struct(fields)) => fields,
+        _ => panic!("{} isn't deserialized as a struct", std::any::type_name::<T>()),
+    }
+}
+
+/// Values of an enum as serde reads them, aliases included. Use them for [`Validator::one_of`].
+///
+/// Panics if `T` isn't deserialized as an externally tagged enum.
+pub fn serde_variants<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    match introspect::<T>() {
+        Some(Introspected::Enum(variants)) => variants,
+        _ => panic!("{} isn't deserialized as an enum", std::any::type_name::<T>()),
+    }
+}
+
+/// Values of the tag of an internally tagged enum, i.e. one with `#[serde(tag = "...")]`, aliases included.
+/// Use them for [`Validator::one_of`].
+///
+/// Panics if `T` isn't deserialized as an internally tagged enum with this tag.
+pub fn serde_tag_values<'de, T: Deserialize<'de>>(tag: &str) -> &'static [&'static str] {
+    // Serde lists the known values when the tag has an unknown one
+    let map = MapDeserializer::new(std::iter::once((tag.to_string(), String::new())));
+    match T::deserialize(map) {
+        Err(UnknownVariant(Some(variants))) => variants,
+        _ => panic!("{} isn't an enum tagged with \"{}\"", std::any::type_name::<T>(), tag),
+    }
+}
+
+/// Error that only keeps the known variants of an enum
+#[derive(Debug)]
+struct UnknownVariant(Option<&'static [&'static str]>);
+
+impl fmt::Display for UnknownVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown variant")
+    }
+}
+
+impl std::error::Error for UnknownVariant {}
+
+impl de::Error for UnknownVariant {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        UnknownVariant(None)
+    }
+
+    fn unknown_variant(_variant: &str, expected: &'static [&'static str]) -> Self {
+        UnknownVariant(Some(expected))
+    }
+}
+
+enum Introspected {
+    Struct(&'static [&'static str]),
+    Enum(&'static [&'static str]),
+}
+
+/// Start deserializing `T` from a deserializer that only records the names serde asks for and then fails
+fn introspect<'de, T: Deserialize<'de>>() -> Option<Introspected> {
+    let mut introspected = None;
+    let _ = T::deserialize(Introspector { introspected: &mut introspected });
+    introspected
+}
+
+struct Introspector<'a> {
+    introspected: &'a mut Option<Introspected>,
+}
+
+impl<'de> Deserializer<'de> for Introspector<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only structs and enums can be introspected"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], _visitor: V) -> Result<V::Value, Self::Error> {
+        *self.introspected = Some(Introspected::Struct(fields));
+        Err(de::Error::custom("introspected"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, variants: &'static [&'static str], _visitor: V) -> Result<V::Value, Self::Error> {
+        *self.introspected = Some(Introspected::Enum(variants));
+        Err(de::Error::custom("introspected"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map identifier ignored_any
+    }
+}
+
+/// Join a parent path and a key, e.g. `location` and `lat` to `location.lat`
+pub fn join_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+fn display_path(path: &str) -> String {
+    if path.is_empty() {
+        "(root)".to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+    path.split('.').try_fold(value, |acc, key| acc.get(key))
+}
+
+/// Find the candidate that is most similar to the text if it is similar enough to be a typo
+fn closest<'b>(text: &str, candidates: impl Iterator<Item = &'b str>) -> Option<&'b str> {
+    candidates
+        .map(|candidate| (candidate, strsim::jaro_winkler(&text.to_lowercase(), &candidate.to_lowercase())))
+        .filter(|(_, similarity)| *similarity > 0.85)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(candidate, _)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn test_serde_names() {
+        #[derive(serde::Deserialize)]
+        #[serde(default)]
+        #[allow(dead_code)]
+        struct Options {
+            name: String,
+            #[serde(rename = "timeout_seconds", alias = "timeout")]
+            timeout: u64,
+            policy: Policy,
+        }
+
+        impl Default for Options {
+            fn default() -> Self {
+                Options { name: String::new(), timeout: 30, policy: Policy::NewestUnseen }
+            }
+        }
+
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "kebab-case")]
+        #[allow(dead_code)]
+        enum Policy {
+            Newest,
+            #[serde(alias = "unseen")]
+            NewestUnseen,
+        }
+
+        // Serde decides where the aliases go
+        let sorted = |names: &[&'static str]| {
+            let mut names = names.to_vec();
+            names.sort();
+            names
+        };
+        assert_eq!(sorted(serde_fields::<Options>()), ["name", "policy", "timeout", "timeout_seconds"]);
+        assert_eq!(sorted(serde_variants::<Policy>()), ["newest", "newest-unseen", "unseen"]);
+
+        #[derive(serde::Deserialize)]
+        #[serde(tag = "provider", rename_all = "kebab-case")]
+        #[allow(dead_code)]
+        enum Provider {
+            Quotes { path: String },
+            #[serde(alias = "rss")]
+            Feed { url: String },
+            Nothing,
+        }
+
+        assert_eq!(sorted(serde_tag_values::<Provider>("provider")), ["feed", "nothing", "quotes", "rss"]);
+    }
+
+    #[test]
+    fn test_integer_in() {
+        let config = json!({ "pages": 2, "hours": 1.5, "days": "2", "minutes": 0 });
+
+        let mut v = Validator::new(&config);
+        assert_eq!(v.integer_in("pages", true, 1, 10), Some(2));
+        assert_eq!(v.integer_in("hours", true, 1, 720), None);
+        assert_eq!(v.integer_in("days", true, 0, 365), None);
+        assert_eq!(v.integer_in("minutes", true, 1, 60), None);
+
+        let issues = v.finish().unwrap_err().0;
+        let messages: Vec<&str> = issues.iter().map(|i| i.message.as_str()).collect();
+        assert_eq!(messages, ["1.5 isn't a whole number", "Expected a whole number, found a string", "0 is out of range"]);
+    }
+
+    #[test]
+    fn test_valid() {
+        let config = json!({
+            "timezone": "Europe/Berlin",
+            "time_format": "%d/%m/%Y %H:%M",
+            "location": { "lat": 52.5 }
+        });
+
+        let mut v = Validator::new(&config);
+        v.object("", true, &["timezone", "time_format", "location"]);
+        v.timezone("timezone", true);
+        v.time_format("time_format", true);
+        v.object("location", true, &["lat"]);
+        v.number_in("location.lat", true, -90.0, 90.0);
+
+        assert_eq!(v.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_reports_all_issues() {
+        let config = json!({
+            "timezone": "Europe/Berln",
+            "time_format": "%d %Q",
+            "weekday_names": { "mondy": "Montag" },
+            "location": { "lat": "52.5" }
+        });
+
+        let mut v = Validator::new(&config);
+        v.object("", true, &["timezone", "time_format", "weekday_names", "location"]);
+        v.timezone("timezone", true);
+        v.time_format("time_format", true);
+        v.object("weekday_names", true, &["monday"]);
+        v.string("weekday_names.monday", true);
+        v.number("location.lat", true);
+
+        let issues = v.finish().unwrap_err().0;
+        let paths: Vec<&str> = issues.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(paths, vec!["timezone", "time_format", "weekday_names.mondy", "weekday_names.monday", "location.lat"]);
+
+        assert_eq!(issues[0].suggestion.as_deref(), Some("Did you mean \"Europe/Berlin\"?"));
+        assert_eq!(issues[2].suggestion.as_deref(), Some("Did you mean \"monday\"?"));
+        assert_eq!(issues[3].suggestion.as_deref(), Some("Add \"monday\" to weekday_names"));
+        assert_eq!(issues[4].message, "Expected a number, found a string");
+    }
+
+    #[test]
+    fn test_one_of() {
+        let config = json!({ "method": "digets" });
+
+        let mut v = Validator::new(&config);
+        assert_eq!(v.one_of("method", true, &["basic", "digest"]), None);
+        assert_eq!(v.one_of("missing", false, &["basic", "digest"]), None);
+
+        let issues = v.finish().unwrap_err().0;
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].suggestion.as_deref(), Some("Did you mean \"digest\"?"));
+    }
+
+    #[test]
+    fn test_lists() {
+        let config = json!({ "types": ["meme", "vidoe", 3], "tags": "anime" });
+
+        let mut v = Validator::new(&config);
+        assert_eq!(v.each_one_of("types", true, &["meme", "video"]), Some(vec!["meme", "vidoe"]));
+        assert_eq!(v.strings("tags", true), None);
+        assert_eq!(v.strings("missing", false), None);
+
+        let issues = v.finish().unwrap_err().0;
+        let paths: Vec<&str> = issues.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(paths, vec!["types[2]", "types[1]", "tags"]);
+        assert_eq!(issues[1].suggestion.as_deref(), Some("Did you mean \"video\"?"));
+        assert_eq!(issues[2].message, "Expected a list, found a string");
+    }
+}
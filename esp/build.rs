@@ -1,9 +1,35 @@
 use anyhow::Result;
+use build_utils::schedule::Schedule;
+use build_utils::validate::Validator;
 
 fn main() -> Result<()> {
     embuild::espidf::sysenv::output();
 
-    let config = build_utils::get_config()?;
+    let config = build_utils::get_config(build_utils::shared::ESP)?;
+
+    // Report all problems at once instead of failing on the first missing field
+    // or later when the generated code doesn't compile
+    let mut v = Validator::new(&config);
+    v.object("", true, &["wifi", "image_url", "timezone", "schedule", "fetch_delay_seconds"]);
+    v.object("wifi", true, &["ssid", "psk"]);
+    v.string("wifi.ssid", true);
+    v.string("wifi.psk", true);
+    v.url("image_url", true);
+    v.timezone("timezone", true);
+    let schedule = v.schedule("schedule", false);
+    v.integer_in("fetch_delay_seconds", false, 0, 3600);
+    v.finish()?;
+
+    // Without a schedule, refresh every hour in daytime and don't refresh at night
+    let schedule = match schedule {
+        Some(schedule) => schedule,
+        None => Schedule::from_value(&serde_json::json!({
+            "cron": "0 * * * *",
+            "quiet_hours": { "start": "23:00", "end": "06:00" }
+        }))?,
+    };
+    // Wait for the new image to be rendered
+    let fetch_delay_seconds = build_utils::get_f64_or(&config, "fetch_delay_seconds", 60.0)? as i64;
 
     let get_str = |key| build_utils::get_str(&config, key);
 
@@ -15,12 +41,16 @@ fn main() -> Result<()> {
                 psk: {:?}
             }},
             image_url: {:?},
-            timezone: chrono_tz::{}
+            timezone: chrono_tz::{},
+            schedule: &{:?},
+            fetch_delay_seconds: {}
         }};",
         get_str("wifi.ssid")?,
         get_str("wifi.psk")?,
         get_str("image_url")?,
         get_str("timezone")?.replace("/", "::"),
+        schedule.minutes_of_week(),
+        fetch_delay_seconds,
     );
 
     build_utils::write_code(&code)?;
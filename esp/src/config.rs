@@ -3,7 +3,11 @@ use crate::wifi;
 pub struct Config {
     pub wifi: wifi::WifiConfig,
     pub image_url: &'static str,
-    pub timezone: chrono_tz::Tz
+    pub timezone: chrono_tz::Tz,
+    /// Refresh times as minutes since Monday 00:00 in local time, sorted
+    pub schedule: &'static [u16],
+    /// Time between a refresh of the schedule and downloading the image, so that the renderer can finish it
+    pub fetch_delay_seconds: i64
 }
 
 include!(concat!(env!("OUT_DIR"), "/config.rs"));
@@ -74,7 +74,7 @@ fn main() -> anyhow::Result<()> {
         log::error!("Getting image data failed: {:?}", image_result.unwrap_err());
     }
 
-    // Get time duration until next hour
+    // Get time duration until the next refresh
     let sleep_time = time::get_sleep_time();
     log::info!("Sleeping {} seconds...", sleep_time.num_seconds());
     time::disable_sntp(sntp);
@@ -83,7 +83,7 @@ fn main() -> anyhow::Result<()> {
     log::info!("Turning off wifi");
     wifi::disable_wifi(wifi)?;
 
-    // deep sleep for 1 hour
+    // Deep sleep until the next refresh
     enter_deep_sleep(sleep_time);
 
     unreachable!("In sleep");
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chrono::{DateTime, Duration, NaiveTime, Timelike, Utc};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, Utc};
 use esp_idf_svc::sntp::{EspSntp, SyncStatus};
 use log::info;
 use crate::config::CONFIG;
@@ -22,43 +22,26 @@ fn get_current_time() -> DateTime<chrono_tz::Tz> {
     Utc::now().with_timezone(&CONFIG.timezone)
 }
 
+/// Time until the next refresh time of the schedule plus the fetch delay
 pub fn get_sleep_time() -> Duration {
     let now = get_current_time();
-
-    // Refresh display every hour in daytime
-    // and don't refresh at night
-    if now.hour() >= 6 && now.hour() <= 22 {
-        time_until_next_hour()
-    } else {
-        time_until_6am()
-    }
-}
-
-fn time_until_next_hour() -> Duration {
-    let now = get_current_time();
     info!("Current time: {:?}", now);
-    
-    let next_hour = now.checked_add_signed(Duration::hours(1)).unwrap();
 
-    // Set an offset of 1 minute to wait for the new image to be generated
-    let next_hour_datetime = next_hour.with_minute(1).unwrap().with_second(0).unwrap().with_nanosecond(0).unwrap();
-    info!("Start of next hour: {:?}", next_hour_datetime);
-    
-    next_hour_datetime.signed_duration_since(now)
-}
+    let fetch_delay = Duration::seconds(CONFIG.fetch_delay_seconds);
+    let next_refresh = next_refresh(CONFIG.schedule, now.naive_local() - fetch_delay) + fetch_delay;
+    info!("Next refresh: {:?}", next_refresh);
 
-fn time_until_6am() -> Duration {
-    let now = get_current_time();
-    info!("Current time: {:?}", now);
-
-    // Calculate the next 6am
-    let next_6am = if now.time() < NaiveTime::from_hms_opt(6, 0, 0).unwrap() {
-        now.date_naive().and_hms_opt(6, 0, 0).unwrap()
-    } else {
-        now.date_naive().succ_opt().unwrap().and_hms_opt(6, 0, 0).unwrap()
-    };
+    next_refresh.signed_duration_since(now.naive_local())
+}
 
-    println!("Next 6am: {:?}", next_6am);
+/// The first time of the schedule after the given local time
+fn next_refresh(schedule: &[u16], after: NaiveDateTime) -> NaiveDateTime {
+    let week_start = (after.date() - Duration::days(after.weekday().num_days_from_monday() as i64))
+        .and_time(NaiveTime::MIN);
 
-    next_6am.signed_duration_since(now.naive_local())
+    // The schedule repeats every week, so this or the next week has a time after now
+    (0..2)
+        .flat_map(|week| schedule.iter().map(move |&m| week_start + Duration::weeks(week) + Duration::minutes(m as i64)))
+        .find(|time| *time > after)
+        .expect("Schedule is empty")
 }
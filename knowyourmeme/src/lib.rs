@@ -1,141 +1,781 @@
+use std::time::Duration;
 use reqwest::blocking::Client;
-use scraper::{Html, Selector};
-use anyhow::{anyhow, bail, Ok, Result};
+use reqwest::{StatusCode, Url};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
 
-const URL: &str = "https://knowyourmeme.com/";
+mod safety;
+pub use safety::{Blocked, SafetyFilter};
+
+/// Where the front page is taken from by default
+pub const DEFAULT_BASE_URL: &str = "https://knowyourmeme.com/";
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+const ARTICLES_SELECTOR: &str = r#"#feed_items > [id^="newsfeed_"]"#;
+const ARTICLE_TITLE_SELECTOR: &str = ".newsfeed-title";
+const ARTICLE_IMAGE_SELECTOR: &str = ".newsfeed_photo";
+const ARTICLE_SUMMARY_SELECTOR: &str = ".summary";
+const ARTICLE_TAGS_SELECTOR: &str = ".tags a";
+const ARTICLE_NSFW_SELECTOR: &str = r#".nsfw, [data-nsfw="true"]"#;
+const PAGE_NSFW_SELECTOR: &str = r#"body.nsfw, meta[name="rating"][content="adult"]"#;
+const MEME_NAME_SELECTOR_DESKTOP: &str = "section.info > h1:nth-child(1)";
+const MEME_NAME_SELECTOR_MOBILE: &str = ".entry-title";
+const MEDIA_MEME_NAME_SELECTOR: &str = "#media-title";
+const MEDIA_ENTRY_LINK_SELECTOR: &str = r#"#media-title a[href^="/memes/"]"#;
+const ENTRY_DETAILS_SELECTOR: &str = "dl > dt";
+const ENTRY_ABOUT_SELECTOR: &str = "#about + p";
+const ENTRY_IMAGE_SELECTOR: &str = r#"meta[property="og:image"]"#;
 
 pub type Feed = Vec<Article>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Article {
     pub url: String,
     pub title: String,
     pub summary: String,
     pub image_url: String,
-    pub meme_name: Option<String>
+    pub meme_name: Option<String>,
+    #[serde(default)]
+    pub entry_type: EntryType,
+    /// Lowercase tags of the article
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Details of the meme the article is about, if it has an entry
+    #[serde(default)]
+    pub meme_entry: Option<MemeEntry>,
+    /// Know Your Meme marks the article or its image as not safe for work
+    #[serde(default)]
+    pub nsfw: bool,
 }
 
-pub fn get_newest_meme_article() -> Result<Article> {
-    let mut article = get_newest_article()?;
-    article.meme_name = get_meme_title(&article.url).ok();
-    Ok(article)
+/// Entry of a meme, read from its `/memes/...` page
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemeEntry {
+    pub url: String,
+    pub name: String,
+    /// E.g. "Confirmed" or "Submission"
+    pub status: Option<String>,
+    /// E.g. "Viral Video" or "Character"
+    pub types: Vec<String>,
+    pub year: Option<u16>,
+    /// Where the meme started, e.g. "YouTube"
+    pub origin: Option<String>,
+    /// Lowercase tags of the entry
+    pub tags: Vec<String>,
+    /// First paragraph of the "About" section
+    pub about: Option<String>,
+    pub image_url: Option<String>,
 }
 
-pub fn get_newest_article() -> Result<Article> {
-    let mut feed = get_feed()?;
+/// Kind of a front page article, taken from its `data-type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    Meme,
+    Image,
+    Video,
+    Editorial,
+    News,
+    /// Types this crate doesn't know yet
+    #[default]
+    #[serde(other)]
+    Other,
+}
 
-    if feed.is_empty() {
-        bail!("No articles found");
+impl EntryType {
+    fn from_data_type(data_type: &str) -> Self {
+        match data_type.to_lowercase().as_str() {
+            "meme" | "entry" => EntryType::Meme,
+            "photo" | "image" => EntryType::Image,
+            "video" => EntryType::Video,
+            "editorial" => EntryType::Editorial,
+            "news" => EntryType::News,
+            _ => EntryType::Other,
+        }
     }
+}
 
-    let article = feed.remove(0);
-    Ok(article)
+/// Which pages of the front page are read and which articles are kept
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FeedOptions {
+    /// Number of front page pages to read, starting with the first
+    pub pages: u32,
+    /// Only keep articles of these types. Every type if empty.
+    pub include_types: Vec<EntryType>,
+    pub exclude_types: Vec<EntryType>,
+    /// Only keep articles with at least one of these tags. Every article if empty.
+    pub tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    /// Applied after the other options
+    pub safety: SafetyFilter,
 }
 
-/// Get all articles on the front page excluding editorials but including the meme names.
-pub fn get_feed_full() -> Result<Feed> {
-    let mut feed = get_feed()?;
-    for article in &mut feed {
-        article.meme_name = get_meme_title(&article.url).ok();
+impl Default for FeedOptions {
+    fn default() -> Self {
+        FeedOptions {
+            pages: 1,
+            include_types: Vec::new(),
+            exclude_types: vec![EntryType::Editorial],
+            tags: Vec::new(),
+            exclude_tags: Vec::new(),
+            safety: SafetyFilter::default(),
+        }
     }
+}
 
-    Ok(feed)
+impl FeedOptions {
+    pub fn matches(&self, article: &Article) -> bool {
+        let has_tag = |tags: &[String]| tags.iter().any(|tag| article.tags.contains(&tag.to_lowercase()));
+
+        (self.include_types.is_empty() || self.include_types.contains(&article.entry_type))
+            && !self.exclude_types.contains(&article.entry_type)
+            && (self.tags.is_empty() || has_tag(&self.tags))
+            && !has_tag(&self.exclude_tags)
+    }
+}
+
+#[derive(Debug)]
+pub enum KymError {
+    /// The base URL of the client isn't a valid URL
+    InvalidBaseUrl(String),
+    /// The request failed, e.g. because there's no connection or it took too long
+    Network(reqwest::Error),
+    /// The server responded with an error
+    Status { url: String, status: StatusCode },
+    /// Something wasn't found on the page, probably because the site changed.
+    /// Missing attributes are part of the selector, e.g. `.newsfeed_photo[data-src]`.
+    Structure { url: String, what: &'static str, selector: String },
+    /// No article of the front page matches the feed options and passes the safety filter,
+    /// also after its meme was read
+    NoArticles,
+}
+
+impl std::fmt::Display for KymError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KymError::InvalidBaseUrl(url) => write!(f, "Invalid base URL \"{}\"", url),
+            KymError::Network(e) => write!(f, "Couldn't reach Know Your Meme: {}", e),
+            KymError::Status { url, status } => write!(f, "{} responded with {}", url, status),
+            KymError::Structure { url, what, selector } => write!(
+                f,
+                "Couldn't find the {} on {} with the selector \"{}\". The page structure probably changed.",
+                what, url, selector
+            ),
+            KymError::NoArticles => write!(f, "No articles found"),
+        }
+    }
+}
+
+impl std::error::Error for KymError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KymError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for KymError {
+    fn from(e: reqwest::Error) -> Self {
+        KymError::Network(e)
+    }
+}
+
+/// Settings of the HTTP client, e.g. to use a mirror
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ClientOptions {
+    /// URL of the front page. Article links are resolved against it.
+    pub base_url: String,
+    pub user_agent: String,
+    /// Timeout of a whole request
+    pub timeout_seconds: u64,
+    pub connect_timeout_seconds: u64,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            timeout_seconds: 30,
+            connect_timeout_seconds: 10,
+        }
+    }
 }
 
-/// Get all articles on the front page.
-/// Doesn't include editorials and doesn't fill in the meme name.
-fn get_feed() -> Result<Feed> {
-    let response = Client::new().get(URL).send()?;
-    if !response.status().is_success() {
-        bail!("Couldn't reach knowyourmeme.com");
+/// Scrapes articles from Know Your Meme. All requests share one connection pool.
+#[derive(Debug, Clone)]
+pub struct KymClient {
+    client: Client,
+    base_url: Url,
+}
+
+impl KymClient {
+    pub fn new(options: &ClientOptions) -> Result<Self, KymError> {
+        let mut base_url = Url::parse(&options.base_url).map_err(|_| KymError::InvalidBaseUrl(options.base_url.clone()))?;
+        // Make sure the URL is treated as a directory when joining, so that mirrors can be in a subdirectory
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        let client = Client::builder()
+            .user_agent(&options.user_agent)
+            .timeout(Duration::from_secs(options.timeout_seconds))
+            .connect_timeout(Duration::from_secs(options.connect_timeout_seconds))
+            .build()?;
+
+        Ok(KymClient { client, base_url })
     }
 
-    let html = response.text()?;
-    let document = Html::parse_document(html.as_str());
+    /// Get the newest article with its meme filled in.
+    /// Articles whose pages turn out to be unsuitable are skipped in favor of the next one.
+    pub fn get_newest_meme_article(&self) -> Result<Article, KymError> {
+        let options = FeedOptions::default();
+        let feed = self.get_feed(&options)?;
+        self.first_safe_meme(feed, &options.safety)
+    }
 
-    let articles_selector = Selector::parse(r#"#feed_items > [id^="newsfeed_"]"#).unwrap();
-    let article_title_selector = Selector::parse(".newsfeed-title").unwrap();
-    let article_image_selector = Selector::parse(".newsfeed_photo").unwrap();
-    let article_summary_selector = Selector::parse(".summary").unwrap();
+    /// Fill in the memes of the articles in order until one still passes the safety filter
+    fn first_safe_meme(&self, feed: Feed, safety: &SafetyFilter) -> Result<Article, KymError> {
+        for mut article in feed {
+            self.fill_meme(&mut article);
+            if is_safe(&article, safety) {
+                return Ok(article);
+            }
+        }
+        Err(KymError::NoArticles)
+    }
+
+    pub fn get_newest_article(&self, options: &FeedOptions) -> Result<Article, KymError> {
+        let mut feed = self.get_feed(options)?;
+
+        if feed.is_empty() {
+            return Err(KymError::NoArticles);
+        }
+
+        let article = feed.remove(0);
+        Ok(article)
+    }
+
+    /// Get the articles of the front page including the meme names and entries.
+    /// The safety filter is applied again, since the pages and entries can make an article unsuitable.
+    pub fn get_feed_full(&self, options: &FeedOptions) -> Result<Feed, KymError> {
+        let mut feed = self.get_feed(options)?;
+        for article in &mut feed {
+            self.fill_meme(article);
+        }
+        feed.retain(|article| is_safe(article, &options.safety));
+
+        Ok(feed)
+    }
+
+    /// Get the articles of the front page that match the options and pass the safety filter, newest first.
+    /// Doesn't fill in the meme name, so check the article with the filter again after [`KymClient::fill_meme`].
+    ///
+    /// Only the first page has to be read successfully. If a later page fails, the articles read so far are returned.
+    pub fn get_feed(&self, options: &FeedOptions) -> Result<Feed, KymError> {
+        let mut feed = Feed::new();
+
+        for page in 1..=options.pages.max(1) {
+            let url = match page {
+                1 => self.base_url.clone(),
+                _ => self.base_url.join(&format!("page/{}", page)).expect("Valid page URL"),
+            };
+            debug!(%url, "Getting front page");
+
+            let articles = match self.get_html(url.clone()).and_then(|html| parse_feed(&html, url.as_str())) {
+                Ok(articles) => articles,
+                Err(e) if page > 1 => {
+                    warn!(page, error = %e, "Couldn't read front page. Using the previous pages.");
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+
+            for article in articles {
+                // Articles move to the next page while the pages are read
+                if !feed.iter().any(|a: &Article| a.url == article.url) {
+                    feed.push(article);
+                }
+            }
+        }
+
+        feed.retain(|article| options.matches(article));
+        feed.retain(|article| is_safe(article, &options.safety));
+        debug!(articles = feed.len(), "Filtered front page");
+        Ok(feed)
+    }
+
+    /// Get the name of the meme the article is about
+    pub fn get_meme_title(&self, article_url: &str) -> Result<String, KymError> {
+        debug!(url = article_url, "Getting meme title");
+        let html = self.get_html(self.article_url(article_url)?)?;
+        parse_meme_title(&html, article_url)
+    }
+
+    /// Get the entry of a meme from its `/memes/...` page
+    pub fn get_meme_entry(&self, entry_url: &str) -> Result<MemeEntry, KymError> {
+        debug!(url = entry_url, "Getting meme entry");
+        let html = self.get_html(self.article_url(entry_url)?)?;
+        parse_meme_entry(&html, entry_url)
+    }
+
+    /// Fill in the name and the entry of the meme the article is about.
+    /// Meme articles are read as entries directly. Images and videos link to their entry, which is read as well.
+    /// The article is marked as NSFW if one of the pages is.
+    /// Failures are logged and leave the fields empty, since the article can be shown without them.
+    pub fn fill_meme(&self, article: &mut Article) {
+        if let Err(e) = self.try_fill_meme(article) {
+            warn!(url = article.url, error = %e, "Couldn't get the meme of the article");
+        }
+    }
+
+    fn try_fill_meme(&self, article: &mut Article) -> Result<(), KymError> {
+        debug!(url = article.url, "Getting meme of the article");
+        let html = self.get_html(self.article_url(&article.url)?)?;
+        article.nsfw |= is_nsfw_page(&html);
+
+        if article.url.starts_with("/memes/") {
+            let entry = parse_meme_entry(&html, &article.url)?;
+            article.meme_name = Some(entry.name.clone());
+            article.meme_entry = Some(entry);
+            return Ok(());
+        }
+
+        article.meme_name = Some(parse_meme_title(&html, &article.url)?);
+        if let Some(entry_url) = parse_entry_link(&html) {
+            debug!(url = entry_url, "Getting meme entry");
+            let entry_html = self.get_html(self.article_url(&entry_url)?)?;
+            article.nsfw |= is_nsfw_page(&entry_html);
+            article.meme_entry = Some(parse_meme_entry(&entry_html, &entry_url)?);
+        }
+        Ok(())
+    }
+
+    fn article_url(&self, article_url: &str) -> Result<Url, KymError> {
+        // Article links are absolute paths, but a mirror might be in a subdirectory
+        self.base_url.join(article_url.trim_start_matches('/'))
+            .map_err(|_| structure_error(article_url, "article link", &format!("{}[href]", ARTICLE_TITLE_SELECTOR)))
+    }
+
+    fn get_html(&self, url: Url) -> Result<String, KymError> {
+        let response = self.client.get(url.clone()).send()?;
+        if !response.status().is_success() {
+            return Err(KymError::Status { url: url.to_string(), status: response.status() });
+        }
+        Ok(response.text()?)
+    }
+}
+
+/// Get the newest article from knowyourmeme.com with the default client
+pub fn get_newest_meme_article() -> Result<Article, KymError> {
+    KymClient::new(&ClientOptions::default())?.get_newest_meme_article()
+}
+
+/// Extract all articles from the HTML of a front page.
+/// Articles without an image and malformed articles are skipped. It's only an error if no article could be read at all.
+pub fn parse_feed(html: &str, url: &str) -> Result<Feed, KymError> {
+    let document = Html::parse_document(html);
 
     let mut feed = Feed::new();
+    let mut first_error = None;
+    let mut found_articles = false;
 
-    // Extract all articles from the homepage
-    for article in document.select(&articles_selector) {
-        // Skip editorials
-        if article.attr("data-type").unwrap() == "Editorial" {
-            continue
+    for article in document.select(&selector(ARTICLES_SELECTOR)) {
+        found_articles = true;
+        match parse_article(article, url) {
+            Ok(Some(article)) => feed.push(article),
+            Ok(None) => {}
+            Err(e) => {
+                warn!(error = %e, "Skipping malformed article");
+                first_error.get_or_insert(e);
+            }
         }
+    }
 
-        let title = article.select(&article_title_selector).next().unwrap();
-        let summary = article.select(&article_summary_selector).next().unwrap();
-        let image = match article.select(&article_image_selector).next() {
-            Some(img) => img,
-            None => continue // Skip article if no image is available
-        };
-        
-        let title_text = title.text().collect::<Vec<_>>().concat();
-        let summary_text = summary.text().collect::<Vec<_>>().concat();
-        let image_url = image.attr("data-src").unwrap().to_string();
-
-        let article_url = title.attr("href").unwrap();
-
-        // Add article entry
-        feed.push(Article {
-            url: article_url.to_string(),
-            title: title_text,
-            summary: summary_text,
-            image_url,
-            meme_name: None
-        });
+    if !found_articles {
+        return Err(structure_error(url, "articles", ARTICLES_SELECTOR));
+    }
+    match first_error {
+        Some(e) if feed.is_empty() => return Err(e),
+        _ => {}
     }
+
+    debug!(articles = feed.len(), "Parsed front page");
     Ok(feed)
 }
 
-/// Get the name of the meme the article is about
-fn get_meme_title(article_url: &str) -> Result<String> {
-    let response = Client::new().get(URL.to_string() + article_url).send()?;
-    if !response.status().is_success() {
-        bail!("Couldn't reach {}", article_url);
+/// Read an article of the front page. Articles without an image are skipped.
+fn parse_article(article: ElementRef, url: &str) -> Result<Option<Article>, KymError> {
+    let data_type = article.attr("data-type")
+        .ok_or_else(|| structure_error(url, "article type", &format!("{}[data-type]", ARTICLES_SELECTOR)))?;
+
+    let title = article.select(&selector(ARTICLE_TITLE_SELECTOR)).next()
+        .ok_or_else(|| structure_error(url, "article title", ARTICLE_TITLE_SELECTOR))?;
+    let summary = article.select(&selector(ARTICLE_SUMMARY_SELECTOR)).next()
+        .ok_or_else(|| structure_error(url, "article summary", ARTICLE_SUMMARY_SELECTOR))?;
+    let image = match article.select(&selector(ARTICLE_IMAGE_SELECTOR)).next() {
+        Some(img) => img,
+        None => return Ok(None) // Skip article if no image is available
+    };
+
+    let title_text = title.text().collect::<Vec<_>>().concat();
+    let summary_text = summary.text().collect::<Vec<_>>().concat();
+    let image_url = image.attr("data-src")
+        .ok_or_else(|| structure_error(url, "article image", &format!("{}[data-src]", ARTICLE_IMAGE_SELECTOR)))?;
+    let article_url = title.attr("href")
+        .ok_or_else(|| structure_error(url, "article link", &format!("{}[href]", ARTICLE_TITLE_SELECTOR)))?;
+
+    // Not every article has tags
+    let tags = article.select(&selector(ARTICLE_TAGS_SELECTOR))
+        .map(|tag| tag.text().collect::<String>().trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    // Either the whole article or only its image can be marked
+    let nsfw = article.value().classes().any(|class| class.eq_ignore_ascii_case("nsfw"))
+        || article.select(&selector(ARTICLE_NSFW_SELECTOR)).next().is_some();
+
+    Ok(Some(Article {
+        url: article_url.to_string(),
+        title: title_text,
+        summary: summary_text,
+        image_url: image_url.to_string(),
+        meme_name: None,
+        entry_type: EntryType::from_data_type(data_type),
+        tags,
+        meme_entry: None,
+        nsfw,
+    }))
+}
+
+fn is_safe(article: &Article, safety: &SafetyFilter) -> bool {
+    match safety.check(article) {
+        Some(reason) => {
+            debug!(url = article.url, %reason, "Skipping unsuitable article");
+            false
+        }
+        None => true,
     }
-    let html = response.text()?;
-    let document = Html::parse_document(html.as_str());
+}
+
+/// Whether the page of an article or entry is marked as not safe for work
+fn is_nsfw_page(html: &str) -> bool {
+    Html::parse_document(html).select(&selector(PAGE_NSFW_SELECTOR)).next().is_some()
+}
+
+/// Extract the name of the meme from the HTML of an article
+pub fn parse_meme_title(html: &str, article_url: &str) -> Result<String, KymError> {
+    let document = Html::parse_document(html);
 
-    let meme_name_selector_desktop = Selector::parse("section.info > h1:nth-child(1)").unwrap();
-    let meme_name_selector_mobile = Selector::parse(".entry-title").unwrap();
+    let name_element = if article_url.starts_with("/memes/") {
+        // Try get meme name assuming is from the desktop page
+        // and try the mobile page selector if the desktop selector failed
+        document.select(&selector(MEME_NAME_SELECTOR_DESKTOP)).next()
+            .or_else(|| document.select(&selector(MEME_NAME_SELECTOR_MOBILE)).next())
+            .ok_or_else(|| structure_error(
+                article_url,
+                "meme name",
+                &format!("{}, {}", MEME_NAME_SELECTOR_DESKTOP, MEME_NAME_SELECTOR_MOBILE)
+            ))?
+    } else {
+        document.select(&selector(MEDIA_MEME_NAME_SELECTOR)).next()
+            .ok_or_else(|| structure_error(article_url, "meme name", MEDIA_MEME_NAME_SELECTOR))?
+    };
 
-    let media_meme_name_selector = Selector::parse("#media-title").unwrap();
+    Ok(name_element.text().collect::<Vec<_>>().concat().trim().to_string())
+}
 
-    println!("Getting meme title from {}", article_url);
+/// Extract the entry of a meme from the HTML of its `/memes/...` page.
+/// Only the name is required, every detail is optional.
+pub fn parse_meme_entry(html: &str, entry_url: &str) -> Result<MemeEntry, KymError> {
+    let name = parse_meme_title(html, entry_url)?;
+    let document = Html::parse_document(html);
 
-    // 
-    if article_url.starts_with("/memes/") {
-        // Try get meme name assuming is from the desktop page 
-        let name_element = document.select(&meme_name_selector_desktop).next();
+    let mut entry = MemeEntry {
+        url: entry_url.to_string(),
+        name,
+        status: None,
+        types: Vec::new(),
+        year: None,
+        origin: None,
+        tags: Vec::new(),
+        about: None,
+        image_url: None,
+    };
 
-        // Try the mobile page selector if the desktop selector failed
-        let name_element = match name_element {
-            Some(element) => element,
-            None => document.select(&meme_name_selector_mobile).next()
-                .ok_or(anyhow!("Couldn't find meme name from {}", article_url))?
+    for term in document.select(&selector(ENTRY_DETAILS_SELECTOR)) {
+        let Some(details) = term.next_siblings().filter_map(ElementRef::wrap).next().filter(|e| e.value().name() == "dd") else {
+            continue;
         };
+        let links: Vec<String> = details.select(&selector("a")).map(text).filter(|link| !link.is_empty()).collect();
 
-        Ok(name_element.text().collect::<Vec<_>>().concat())
-    } else {
-        let name_element = document.select(&media_meme_name_selector).next()
-            .ok_or(anyhow!("Couldn't find meme name from {}", article_url))?;
-        
-        Ok(name_element.text().collect::<Vec<_>>().concat())
+        match text(term).trim_end_matches(':').to_lowercase().as_str() {
+            "status" => entry.status = Some(text(details)).filter(|status| !status.is_empty()),
+            "type" => entry.types = links,
+            "year" => entry.year = text(details).parse().ok(),
+            "origin" => entry.origin = Some(text(details)).filter(|origin| !origin.is_empty()),
+            "tags" => entry.tags = links.iter().map(|tag| tag.to_lowercase()).collect(),
+            _ => {}
+        }
     }
+
+    entry.about = document.select(&selector(ENTRY_ABOUT_SELECTOR)).next()
+        .map(text)
+        .filter(|about| !about.is_empty());
+    entry.image_url = document.select(&selector(ENTRY_IMAGE_SELECTOR)).next()
+        .and_then(|meta| meta.attr("content"))
+        .map(str::to_string);
+
+    Ok(entry)
+}
+
+/// Link from an image or video page to the entry of its meme
+fn parse_entry_link(html: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let link = document.select(&selector(MEDIA_ENTRY_LINK_SELECTOR)).next()?;
+    link.attr("href").map(str::to_string)
+}
+
+/// Text of the element with the whitespace of the HTML collapsed
+fn text(element: ElementRef) -> String {
+    element.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn selector(selector: &str) -> Selector {
+    Selector::parse(selector).expect("Invalid selector")
+}
+
+fn structure_error(url: &str, what: &'static str, selector: &str) -> KymError {
+    KymError::Structure { url: url.to_string(), what, selector: selector.to_string() }
 }
 
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread;
+    use tiny_http::{Response, Server};
     use super::*;
 
+    fn fixture(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+        fs::read_to_string(path).unwrap()
+    }
+
     #[test]
+    #[ignore = "needs access to knowyourmeme.com, run with --ignored"]
     fn test() {
-        assert!(get_feed().is_ok());
+        assert!(KymClient::new(&ClientOptions::default()).unwrap().get_feed(&FeedOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_parse_feed() {
+        let feed = parse_feed(&fixture("homepage.html"), DEFAULT_BASE_URL).unwrap();
+
+        // The article without an image and the malformed article are skipped
+        assert_eq!(feed.len(), 3);
+        assert_eq!(feed[0].entry_type, EntryType::Editorial);
+        assert_eq!(feed[1].url, "/memes/skibidi-toilet");
+        assert_eq!(feed[1].title, "Skibidi Toilet");
+        assert_eq!(feed[1].image_url, "https://i.kym-cdn.com/entries/icons/original/000/044/632/skibidi.jpg");
+        assert!(feed[1].summary.contains("YouTube series"));
+        assert_eq!(feed[1].entry_type, EntryType::Meme);
+        assert_eq!(feed[1].tags, ["youtube", "animation"]);
+        assert_eq!(feed[2].url, "/photos/2863012-distracted-boyfriend");
+        assert_eq!(feed[2].entry_type, EntryType::Image);
+
+        // Editorials are excluded by default
+        let feed: Vec<_> = feed.into_iter().filter(|article| FeedOptions::default().matches(article)).collect();
+        assert_eq!(feed.len(), 2);
+    }
+
+    #[test]
+    fn test_changed_page() {
+        let error = parse_feed("<html><body><div id=\"content\"></div></body></html>", DEFAULT_BASE_URL).unwrap_err();
+        assert!(matches!(error, KymError::Structure { what: "articles", .. }));
+
+        // Only malformed articles
+        let html = r#"<div id="feed_items"><div id="newsfeed_1" data-type="Meme"><img class="newsfeed_photo"></div></div>"#;
+        let error = parse_feed(html, DEFAULT_BASE_URL).unwrap_err();
+        assert!(matches!(error, KymError::Structure { what: "article title", .. }));
+        assert!(error.to_string().contains(".newsfeed-title"));
+    }
+
+    #[test]
+    fn test_parse_meme_title() {
+        assert_eq!(parse_meme_title(&fixture("meme.html"), "/memes/skibidi-toilet").unwrap(), "Skibidi Toilet");
+        assert_eq!(parse_meme_title(&fixture("photo.html"), "/photos/2863012-distracted-boyfriend").unwrap(), "Distracted Boyfriend");
+
+        let error = parse_meme_title(&fixture("photo.html"), "/memes/distracted-boyfriend").unwrap_err();
+        assert!(matches!(error, KymError::Structure { what: "meme name", .. }));
+    }
+
+    #[test]
+    fn test_parse_meme_entry() {
+        let entry = parse_meme_entry(&fixture("meme.html"), "/memes/skibidi-toilet").unwrap();
+        assert_eq!(entry, MemeEntry {
+            url: "/memes/skibidi-toilet".to_string(),
+            name: "Skibidi Toilet".to_string(),
+            status: Some("Confirmed".to_string()),
+            types: vec!["Viral Video".to_string(), "Character".to_string()],
+            year: Some(2023),
+            origin: Some("YouTube".to_string()),
+            tags: vec!["skibidi".to_string(), "toilet".to_string()],
+            about: Some("Skibidi Toilet is a YouTube series of animated videos.".to_string()),
+            image_url: Some("https://i.kym-cdn.com/entries/icons/original/000/044/632/skibidi.jpg".to_string()),
+        });
+
+        // Mobile page without most details
+        let entry = parse_meme_entry(&fixture("meme_minimal.html"), "/memes/distracted-boyfriend").unwrap();
+        assert_eq!(entry.name, "Distracted Boyfriend");
+        assert_eq!(entry.year, None);
+        assert_eq!(entry.status, None);
+        assert!(entry.types.is_empty());
+
+        assert_eq!(parse_entry_link(&fixture("photo.html")).as_deref(), Some("/memes/distracted-boyfriend"));
+        assert_eq!(parse_entry_link(&fixture("meme.html")), None);
+
+        let error = parse_meme_entry(&fixture("photo.html"), "/memes/distracted-boyfriend").unwrap_err();
+        assert!(matches!(error, KymError::Structure { what: "meme name", .. }));
+    }
+
+    /// Serve the fixtures like a mirror in the subdirectory `/kym/` and report the user agent of every request
+    fn fixture_server() -> (String, mpsc::Receiver<String>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/kym/", server.server_addr().to_ip().unwrap());
+        let (sender, user_agents) = mpsc::channel();
+
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let user_agent = request.headers().iter()
+                    .find(|h| h.field.equiv("User-Agent"))
+                    .map(|h| h.value.to_string())
+                    .unwrap_or_default();
+                // Not every test checks the user agents
+                let _ = sender.send(user_agent);
+
+                let name = match request.url() {
+                    "/kym/" => "homepage.html",
+                    "/kym/page/2" => "homepage_page2.html",
+                    "/kym/memes/skibidi-toilet" => "meme.html",
+                    "/kym/photos/2863012-distracted-boyfriend" => "photo.html",
+                    "/kym/memes/distracted-boyfriend" => "meme_minimal.html",
+                    "/kym/memes/cursed-images" => "meme_nsfw.html",
+                    _ => {
+                        request.respond(Response::empty(404)).unwrap();
+                        continue;
+                    }
+                };
+                request.respond(Response::from_string(fixture(name))).unwrap();
+            }
+        });
+
+        (base_url, user_agents)
+    }
+
+    #[test]
+    fn test_client() {
+        let (base_url, user_agents) = fixture_server();
+        let options = ClientOptions { base_url: base_url.trim_end_matches('/').to_string(), user_agent: "test-agent".to_string(), ..Default::default() };
+        let client = KymClient::new(&options).unwrap();
+
+        let feed = client.get_feed_full(&FeedOptions::default()).unwrap();
+        assert_eq!(feed.len(), 2);
+        assert_eq!(feed[0].meme_name.as_deref(), Some("Skibidi Toilet"));
+        assert_eq!(feed[1].meme_name.as_deref(), Some("Distracted Boyfriend"));
+        // The entry of the meme is read from the same page as the name
+        assert_eq!(feed[0].meme_entry.as_ref().unwrap().status.as_deref(), Some("Confirmed"));
+        // The photo links to the entry of its meme
+        assert_eq!(feed[1].meme_entry.as_ref().unwrap().url, "/memes/distracted-boyfriend");
+        assert_eq!(user_agents.try_iter().collect::<Vec<_>>(), vec!["test-agent"; 4]);
+
+        let options = ClientOptions { base_url: format!("{}missing/", base_url), ..Default::default() };
+        let error = KymClient::new(&options).unwrap().get_newest_article(&FeedOptions::default()).unwrap_err();
+        assert!(matches!(error, KymError::Status { status: StatusCode::NOT_FOUND, .. }));
+
+        let options = ClientOptions { base_url: "not a url".to_string(), ..Default::default() };
+        assert!(matches!(KymClient::new(&options), Err(KymError::InvalidBaseUrl(_))));
+    }
+
+    #[test]
+    fn test_feed_options() {
+        let (base_url, _) = fixture_server();
+        let client = KymClient::new(&ClientOptions { base_url, ..Default::default() }).unwrap();
+        let urls = |options: FeedOptions| -> Vec<String> {
+            client.get_feed(&options).unwrap().into_iter().map(|article| article.url).collect()
+        };
+
+        // The article that moved to the second page is only included once
+        assert_eq!(urls(FeedOptions { pages: 2, ..Default::default() }), [
+            "/memes/skibidi-toilet",
+            "/photos/2863012-distracted-boyfriend",
+            "/videos/401234-dancing-cat",
+            "/news/meme-of-the-year",
+        ]);
+        // Page 3 doesn't exist
+        assert_eq!(urls(FeedOptions { pages: 3, ..Default::default() }).len(), 4);
+
+        assert_eq!(
+            urls(FeedOptions { pages: 2, include_types: vec![EntryType::Video, EntryType::Editorial], exclude_types: vec![], ..Default::default() }),
+            ["/editorials/the-best-memes-of-the-week", "/videos/401234-dancing-cat"]
+        );
+        assert_eq!(urls(FeedOptions { pages: 2, tags: vec!["Cat".to_string(), "YouTube".to_string()], ..Default::default() }), [
+            "/memes/skibidi-toilet",
+            "/videos/401234-dancing-cat",
+        ]);
+        assert_eq!(urls(FeedOptions { exclude_tags: vec!["stock photo".to_string()], ..Default::default() }), ["/memes/skibidi-toilet"]);
+    }
+
+    #[test]
+    fn test_safety_filter() {
+        let feed = parse_feed(&fixture("homepage_page2.html"), DEFAULT_BASE_URL).unwrap();
+        let nsfw: Vec<&str> = feed.iter().filter(|article| article.nsfw).map(|article| article.url.as_str()).collect();
+        // The whole article or only the image can be marked
+        assert_eq!(nsfw, ["/memes/cursed-images", "/photos/2900001-cursed-images"]);
+
+        let (base_url, _) = fixture_server();
+        let client = KymClient::new(&ClientOptions { base_url, ..Default::default() }).unwrap();
+        let urls = |safety: SafetyFilter| -> Vec<String> {
+            let options = FeedOptions { pages: 2, safety, ..Default::default() };
+            client.get_feed(&options).unwrap().into_iter().map(|article| article.url).collect()
+        };
+
+        // NSFW articles are skipped by default
+        assert_eq!(urls(SafetyFilter::default()).len(), 4);
+        assert_eq!(urls(SafetyFilter { allow_nsfw: true, ..Default::default() }).len(), 6);
+
+        let blocked = |keywords: &[&str]| keywords.iter().map(|keyword| keyword.to_string()).collect();
+        assert_eq!(urls(SafetyFilter { blocked_keywords: blocked(&["Toilet", "stock photo"]), ..Default::default() }), [
+            "/videos/401234-dancing-cat",
+            "/news/meme-of-the-year",
+        ]);
+        assert_eq!(
+            urls(SafetyFilter { blocked_keywords: blocked(&["toilet"]), allowed_keywords: blocked(&["youtube"]), ..Default::default() }).len(),
+            4
+        );
+
+        // The page of an article can be marked even if the front page isn't
+        let mut feed = parse_feed(&fixture("homepage_page2.html"), DEFAULT_BASE_URL).unwrap();
+        let article = feed.iter_mut().find(|article| article.url == "/memes/cursed-images").unwrap();
+        article.nsfw = false;
+        client.fill_meme(article);
+        assert!(article.nsfw);
+        assert!(!is_nsfw_page(&fixture("meme.html")));
+        assert!(!is_nsfw_page(&fixture("photo.html")));
+
+        // The newest article is skipped if its page is marked
+        let mut feed = parse_feed(&fixture("homepage_page2.html"), DEFAULT_BASE_URL).unwrap();
+        feed.retain(|article| ["/memes/cursed-images", "/photos/2863012-distracted-boyfriend"].contains(&article.url.as_str()));
+        feed.reverse();
+        feed[0].nsfw = false;
+        let article = client.first_safe_meme(feed.clone(), &SafetyFilter::default()).unwrap();
+        assert_eq!(article.url, "/photos/2863012-distracted-boyfriend");
+        assert_eq!(article.meme_name.as_deref(), Some("Distracted Boyfriend"));
+        assert!(matches!(client.first_safe_meme(feed[..1].to_vec(), &SafetyFilter::default()), Err(KymError::NoArticles)));
     }
 }
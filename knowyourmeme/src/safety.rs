@@ -0,0 +1,142 @@
+use std::fmt;
+use serde::Deserialize;
+use crate::Article;
+
+/// Skips articles that shouldn't be shown in public, e.g. on a display in a shared hallway
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct SafetyFilter {
+    /// Keep articles that Know Your Meme marks as NSFW
+    pub allow_nsfw: bool,
+    /// Skip articles with one of these words or phrases in the title, summary, tags or meme name,
+    /// or in the tags and the about text of the meme entry
+    pub blocked_keywords: Vec<String>,
+    /// Keep articles with one of these words or phrases even if they contain a blocked keyword.
+    /// NSFW articles are skipped anyway.
+    pub allowed_keywords: Vec<String>,
+}
+
+/// Why an article was skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Blocked {
+    Nsfw,
+    Keyword(String),
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Blocked::Nsfw => write!(f, "marked as NSFW"),
+            Blocked::Keyword(keyword) => write!(f, "contains the blocked keyword \"{}\"", keyword),
+        }
+    }
+}
+
+impl SafetyFilter {
+    /// Returns why the article must not be shown, or `None` if it's fine
+    pub fn check(&self, article: &Article) -> Option<Blocked> {
+        if article.nsfw && !self.allow_nsfw {
+            return Some(Blocked::Nsfw);
+        }
+
+        let text = searchable_text(article);
+        let contains = |keyword: &String| contains_phrase(&text, &keyword.to_lowercase());
+
+        let blocked = self.blocked_keywords.iter().find(|keyword| contains(keyword))?;
+        if self.allowed_keywords.iter().any(contains) {
+            return None;
+        }
+        Some(Blocked::Keyword(blocked.clone()))
+    }
+
+    pub fn allows(&self, article: &Article) -> bool {
+        self.check(article).is_none()
+    }
+}
+
+/// Lowercase text of the article that keywords are searched in
+fn searchable_text(article: &Article) -> String {
+    let entry = article.meme_entry.as_ref();
+    [&article.title, &article.summary].into_iter()
+        .chain(&article.tags)
+        .chain(&article.meme_name)
+        .chain(entry.into_iter().flat_map(|entry| &entry.tags))
+        .chain(entry.and_then(|entry| entry.about.as_ref()))
+        .map(|text| text.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether the phrase occurs as whole words, e.g. "cat" in "a cat." but not in "category"
+fn contains_phrase(text: &str, phrase: &str) -> bool {
+    let phrase = phrase.trim();
+    if phrase.is_empty() {
+        return false;
+    }
+
+    text.match_indices(phrase).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + phrase.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EntryType, MemeEntry};
+    use super::*;
+
+    fn article(title: &str, tags: &[&str]) -> Article {
+        Article {
+            url: "/memes/test".to_string(),
+            title: title.to_string(),
+            summary: "A summary.".to_string(),
+            image_url: String::new(),
+            meme_name: None,
+            entry_type: EntryType::Meme,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            meme_entry: None,
+            nsfw: false,
+        }
+    }
+
+    fn keywords(keywords: &[&str]) -> Vec<String> {
+        keywords.iter().map(|keyword| keyword.to_string()).collect()
+    }
+
+    #[test]
+    fn test_keywords() {
+        let filter = SafetyFilter { blocked_keywords: keywords(&["Drugs", "gore"]), allowed_keywords: keywords(&["war on drugs"]), ..Default::default() };
+
+        assert!(filter.allows(&article("Dancing Cat", &["cat"])));
+        assert_eq!(filter.check(&article("Memes About drugs", &[])), Some(Blocked::Keyword("Drugs".to_string())));
+        assert_eq!(filter.check(&article("Dancing Cat", &["gore"])), Some(Blocked::Keyword("gore".to_string())));
+        // Only whole words are blocked
+        assert!(filter.allows(&article("Gorey Details", &["drugstore"])));
+        // Allowed keywords override blocked ones
+        assert!(filter.allows(&article("The War On Drugs", &[])));
+
+        let mut nsfw = article("Dancing Cat", &[]);
+        nsfw.nsfw = true;
+        assert_eq!(filter.check(&nsfw), Some(Blocked::Nsfw));
+        assert!(SafetyFilter { allow_nsfw: true, ..Default::default() }.allows(&nsfw));
+
+        // The meme entry is only known after the article was filled in
+        let mut filled = article("Dancing Cat", &[]);
+        filled.meme_entry = Some(MemeEntry {
+            url: "/memes/dancing-cat".to_string(),
+            name: "Dancing Cat".to_string(),
+            status: None,
+            types: Vec::new(),
+            year: None,
+            origin: None,
+            tags: vec!["gore".to_string()],
+            about: None,
+            image_url: None,
+        });
+        assert_eq!(filter.check(&filled), Some(Blocked::Keyword("gore".to_string())));
+        filled.meme_entry.as_mut().unwrap().tags.clear();
+        filled.meme_entry.as_mut().unwrap().about = Some("A cat dancing to songs about drugs.".to_string());
+        assert_eq!(filter.check(&filled), Some(Blocked::Keyword("Drugs".to_string())));
+    }
+}
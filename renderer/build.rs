@@ -1,64 +1,35 @@
+use std::fs;
+use std::path::Path;
 use anyhow::Result;
+use build_utils::format::{self, ConfigFormat};
+use build_utils::shared;
 
 fn main() -> Result<()> {
-    let config = build_utils::get_config()?;
+    // The config is loaded at runtime. If there is a config file at build time,
+    // it gets embedded as the default for when no config path is given.
+    let embedded = match format::find(Path::new(".")) {
+        Some(path) => Some((ConfigFormat::from_path(&path)?, fs::read_to_string(&path)?)),
+        // Only embed the renderer section of the shared config, so that the ESP's secrets don't end up in the binary
+        None => match format::find(Path::new("..")) {
+            Some(path) => {
+                let section = shared::section(&format::read(&path)?, shared::RENDERER)?;
+                for warning in &section.warnings {
+                    println!("cargo:warning={}", warning);
+                }
+                Some((ConfigFormat::Json5, serde_json::to_string_pretty(&section.config)?))
+            }
+            None => None,
+        },
+    };
 
-    let get_str = |key| build_utils::get_str(&config, key);
-    let get_f64 = |key| build_utils::get_f64(&config, key);
-
-    // Generate Rust code to create a Config instance
-    let code = format!(
-        "pub const CONFIG: Config = Config {{
-            webdav_url: {:?},
-            image_name: {:?},
-            location: LatLon {{
-                lat: {},
-                lon: {}
-            }},
-            timezone: chrono_tz::{},
-            time_format: {:?},
-            weekday_names: WeekdayNames {{
-                monday: {:?},
-                tuesday: {:?},
-                wednesday: {:?},
-                thursday: {:?},
-                friday: {:?},
-                saturday: {:?},
-                sunday: {:?},
-            }},
-            weather_conditions: WeatherConditions {{
-                dry: {:?},
-                fog: {:?},
-                rain: {:?},
-                sleet: {:?},
-                snow: {:?},
-                hail: {:?},
-                thunderstorm: {:?},
-                null: {:?},
-            }},
-        }};",
-        get_str("webdav_url")?,
-        get_str("image_name")?,
-        get_f64("location.lat")?,
-        get_f64("location.lon")?,
-        get_str("timezone")?.replace("/", "::"),
-        get_str("time_format")?,
-        get_str("weekday_names.monday")?,
-        get_str("weekday_names.tuesday")?,
-        get_str("weekday_names.wednesday")?,
-        get_str("weekday_names.thursday")?,
-        get_str("weekday_names.friday")?,
-        get_str("weekday_names.saturday")?,
-        get_str("weekday_names.sunday")?,
-        get_str("weather_conditions.dry")?,
-        get_str("weather_conditions.fog")?,
-        get_str("weather_conditions.rain")?,
-        get_str("weather_conditions.sleet")?,
-        get_str("weather_conditions.snow")?,
-        get_str("weather_conditions.hail")?,
-        get_str("weather_conditions.thunderstorm")?,
-        get_str("weather_conditions.null")?,
-    );
+    let code = match embedded {
+        Some((format, text)) => format!(
+            "pub const EMBEDDED_CONFIG: Option<(ConfigFormat, &str)> = Some((ConfigFormat::{:?}, {:?}));",
+            format,
+            text
+        ),
+        None => "pub const EMBEDDED_CONFIG: Option<(ConfigFormat, &str)> = None;".to_string(),
+    };
 
     build_utils::write_code(&code)?;
 
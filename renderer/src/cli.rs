@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+
+use crate::logging::LogFormat;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Renders the image for the e-ink weather display")]
+pub struct Cli {
+    /// Path of the config file. Defaults to $RENDERER_CONFIG or the config embedded at build time.
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+
+    /// Minimum level of log messages, e.g. `debug`, or levels per crate like `info,knowyourmeme=debug`
+    #[arg(long, global = true, env = "RENDERER_LOG", default_value = "info")]
+    pub log_level: String,
+
+    /// Format of log messages, which are written to stderr
+    #[arg(long, global = true, env = "RENDERER_LOG_FORMAT", value_enum, default_value_t)]
+    pub log_format: LogFormat,
+
+    /// What to do. Without a command, the renderer runs as a service.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Render a new image at every refresh time of the `schedule`, upload it to WebDAV and serve it, depending on the config.
+    /// SIGHUP reloads the config and SIGTERM stops after the current render.
+    Run,
+
+    /// Render with live data and save the image locally without uploading or serving it
+    Render {
+        /// Render a single image and exit instead of following the `schedule`
+        #[arg(long)]
+        once: bool,
+
+        /// Where the PNG is saved
+        #[arg(long, default_value = "output.png")]
+        out: PathBuf,
+    },
+
+    /// Render a new image at every refresh time of the `schedule` and only serve it over HTTP without uploading it
+    Serve {
+        /// Address to listen on. Defaults to server_address of the config or 0.0.0.0:8080.
+        #[arg(long)]
+        address: Option<String>,
+    },
+
+    /// Render a single image and upload it to the WebDAV server of the config
+    Upload,
+
+    /// Get the weather and the newest meme and print them
+    Fetch {
+        /// Print the data as JSON that can be used with `preview --fixture`
+        #[arg(long)]
+        json: bool,
+
+        /// Time to get the data for, e.g. 2024-12-24T08:00 in the configured timezone
+        #[arg(long)]
+        at: Option<String>,
+    },
+
+    /// Check the config and print all problems
+    ValidateConfig,
+
+    /// Render a single image at any time and save it locally
+    Preview {
+        /// Time to render the image for, e.g. 2024-12-24T08:00 in the configured timezone.
+        /// Defaults to the time of the fixture or now.
+        #[arg(long)]
+        at: Option<String>,
+
+        /// Render from data written by `fetch --json` instead of getting it from the network
+        #[arg(long)]
+        fixture: Option<PathBuf>,
+
+        /// Where the PNG is saved
+        #[arg(long, default_value = "output.png")]
+        out: PathBuf,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["renderer", "preview", "--fixture", "data.json", "--config", "config.toml"]);
+        assert_eq!(cli.config.as_deref(), Some("config.toml"));
+        assert!(matches!(cli.command, Some(Command::Preview { fixture: Some(_), at: None, .. })));
+
+        let cli = Cli::parse_from(["renderer"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.log_format, LogFormat::Text);
+
+        let cli = Cli::parse_from(["renderer", "run", "--log-format", "json", "--log-level", "debug"]);
+        assert_eq!(cli.log_format, LogFormat::Json);
+        assert_eq!(cli.log_level, "debug");
+    }
+}
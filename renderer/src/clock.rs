@@ -0,0 +1,84 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use anyhow::{anyhow, Result};
+
+/// Source of the current time.
+/// Everything time dependent asks the clock instead of the system time so that it can be frozen.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current time in the given timezone
+    fn now_in(&self, timezone: &Tz) -> DateTime<Tz> {
+        self.now().with_timezone(timezone)
+    }
+}
+
+/// Clock that returns the system time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that always returns the same point in time
+pub struct FixedClock(DateTime<Utc>);
+
+impl FixedClock {
+    pub fn new<T: TimeZone>(date_time: DateTime<T>) -> Self {
+        FixedClock(date_time.with_timezone(&Utc))
+    }
+
+    /// Parse a local time like `2024-12-24T08:00` in the given timezone.
+    /// Seconds and RFC 3339 timestamps with an offset are accepted too.
+    pub fn parse(text: &str, timezone: &Tz) -> Result<Self> {
+        if let Ok(date_time) = DateTime::parse_from_rfc3339(text) {
+            return Ok(FixedClock::new(date_time));
+        }
+
+        let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M")
+            .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S"))
+            .map_err(|_| anyhow!("Invalid time {:?}. Expected a format like 2024-12-24T08:00", text))?;
+
+        let date_time = timezone.from_local_datetime(&naive).earliest()
+            .ok_or(anyhow!("{} doesn't exist in timezone {}", naive, timezone.name()))?;
+
+        Ok(FixedClock::new(date_time))
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+    use super::*;
+
+    const TIME_ZONE: Tz = chrono_tz::Europe::Berlin;
+
+    #[test]
+    fn test_parse_local_time() {
+        let clock = FixedClock::parse("2024-12-24T08:00", &TIME_ZONE).unwrap();
+
+        assert_eq!(clock.now().hour(), 7);
+        assert_eq!(clock.now_in(&TIME_ZONE).hour(), 8);
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let clock = FixedClock::parse("2024-07-01T12:30:00+00:00", &TIME_ZONE).unwrap();
+
+        assert_eq!(clock.now_in(&TIME_ZONE).hour(), 14);
+        assert_eq!(clock.now_in(&TIME_ZONE).minute(), 30);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(FixedClock::parse("24.12.2024 08:00", &TIME_ZONE).is_err());
+    }
+}
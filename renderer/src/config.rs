@@ -1,34 +1,474 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{info, warn};
 use brightsky::LatLon;
+use build_utils::format::{self, ConfigFormat};
+use build_utils::overrides::{self, Environment};
+use build_utils::schedule::Schedule;
+use build_utils::shared;
+use build_utils::validate::{join_path, serde_fields, serde_tag_values, serde_variants, ValidationError, Validator};
+use knowyourmeme::{EntryType, SafetyFilter};
+use crate::content::{ContentOptions, ImageFit};
+use crate::dithering::{parse_hex_color, DitherAlgorithm, DitherOptions};
+use crate::feed_provider::RssFeedOptions;
+use crate::images::ImageOptions;
+use crate::meme_history::{MemeSelection, SelectionPolicy};
+use crate::photo_provider::PhotoOptions;
+use crate::quote_provider::QuoteOptions;
+use crate::webdav::{AuthMethod, WebDavAuth};
 
+/// Environment variable with the path of the config file
+pub const CONFIG_PATH_ENV: &str = "RENDERER_CONFIG";
+/// Prefix of the environment variables that override config values, e.g. `RENDERER__WEBDAV_AUTH__PASSWORD`
+pub const CONFIG_OVERRIDE_PREFIX: &str = "RENDERER";
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct WeekdayNames {
-    pub monday: &'static str,
-    pub tuesday: &'static str,
-    pub wednesday: &'static str,
-    pub thursday: &'static str,
-    pub friday: &'static str,
-    pub saturday: &'static str,
-    pub sunday: &'static str,
+    pub monday: String,
+    pub tuesday: String,
+    pub wednesday: String,
+    pub thursday: String,
+    pub friday: String,
+    pub saturday: String,
+    pub sunday: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
 pub struct WeatherConditions {
-    pub dry: &'static str,
-    pub fog: &'static str,
-    pub rain: &'static str,
-    pub sleet: &'static str,
-    pub snow: &'static str,
-    pub hail: &'static str,
-    pub thunderstorm: &'static str,
-    pub null: &'static str,
+    pub dry: String,
+    pub fog: String,
+    pub rain: String,
+    pub sleet: String,
+    pub snow: String,
+    pub hail: String,
+    pub thunderstorm: String,
+    pub null: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
 pub struct Config {
-    pub webdav_url: &'static str,
-    pub image_name: &'static str,
+    /// WebDAV directory the image gets uploaded to
+    pub webdav_url: Option<String>,
+    pub webdav_auth: Option<WebDavAuth>,
+    pub image_name: String,
+    /// Address of the built-in HTTP server that serves the image, e.g. `0.0.0.0:8080`
+    pub server_address: Option<String>,
     pub location: LatLon,
     pub timezone: chrono_tz::Tz,
-    pub time_format: &'static str,
+    pub time_format: String,
     pub weekday_names: WeekdayNames,
     pub weather_conditions: WeatherConditions,
+    #[serde(default)]
+    pub meme_dithering: DitherOptions,
+    /// When new images are rendered. Every full hour by default.
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// `/healthz` fails when the image is older than this.
+    /// By default it fails when a scheduled refresh is more than 10 minutes late.
+    pub max_image_age_minutes: Option<u32>,
+    /// HTTP client for Know Your Meme, e.g. to use a mirror
+    #[serde(default)]
+    pub knowyourmeme: knowyourmeme::ClientOptions,
+    /// Which article of the front page is shown
+    #[serde(default)]
+    pub meme_selection: MemeSelection,
+    /// What the lower panel shows. Know Your Meme articles by default.
+    #[serde(default)]
+    pub content: ContentOptions,
+    /// Downloads and cache of the images in the lower panel
+    #[serde(default)]
+    pub images: ImageOptions,
+}
+
+impl Config {
+    /// Load the config from the given path, the path in `RENDERER_CONFIG`
+    /// or the config file that was embedded at build time, in this order.
+    /// The format is chosen by the file extension.
+    /// Placeholders and overrides from the environment are resolved afterwards.
+    pub fn load(path: Option<&str>) -> Result<Config> {
+        let path = path.map(PathBuf::from).or_else(|| env::var_os(CONFIG_PATH_ENV).map(PathBuf::from));
+
+        let (format, text) = match path {
+            Some(path) => {
+                info!(path = %path.display(), "Loading config");
+                let format = ConfigFormat::from_path(&path)?;
+                let text = fs::read_to_string(&path).with_context(|| format!("Failed to read config file {}", path.display()))?;
+                (format, text)
+            }
+            None => {
+                let (format, text) = EMBEDDED_CONFIG
+                    .with_context(|| format!("No config found. Use --config <path> or set {}.", CONFIG_PATH_ENV))?;
+                info!("Using embedded config");
+                (format, text.to_string())
+            }
+        };
+
+        Config::parse(&text, format, &Environment::from_process())
+    }
+
+    /// Parse the text of a config file, resolve it with the environment and validate it.
+    /// Shared configs of the renderer and the ESP are supported as well.
+    pub fn parse(text: &str, format: ConfigFormat, environment: &Environment) -> Result<Config> {
+        let mut value: Value = format::parse(text, format).context("Failed to parse config")?;
+        if shared::is_shared(&value) {
+            let section = shared::section(&value, shared::RENDERER)?;
+            for warning in section.warnings {
+                warn!("{}", warning);
+            }
+            value = section.config;
+        }
+        overrides::resolve(&mut value, CONFIG_OVERRIDE_PREFIX, environment)?;
+        validate(&value)?;
+
+        let config: Config = serde_json::from_value(value).context("Failed to parse config")?;
+        if config.webdav_url.is_none() && config.server_address.is_none() {
+            warn!("Neither webdav_url nor server_address is configured. The rendered image won't be available to the display.");
+        }
+
+        Ok(config)
+    }
+}
+
+const PALETTES: &[&str] = &["black-white", "black-white-red"];
+
+/// Check the config for all problems at once, so that they can be fixed in one go.
+/// The known keys and values are taken from the config types, so they can't get out of sync.
+pub fn validate(value: &Value) -> Result<(), ValidationError> {
+    let mut v = Validator::new(value);
+
+    v.object("", true, serde_fields::<Config>());
+
+    v.url("webdav_url", false);
+    if v.object("webdav_auth", false, serde_fields::<WebDavAuth>()) {
+        v.string("webdav_auth.username", true);
+        v.string("webdav_auth.password", true);
+        v.one_of("webdav_auth.method", false, serde_variants::<AuthMethod>());
+    }
+    v.string("image_name", true);
+
+    if let Some(address) = v.string("server_address", false) {
+        let valid = address.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !valid {
+            v.issue("server_address", format!("Invalid address \"{}\"", address), Some("Use host and port like \"0.0.0.0:8080\"".to_string()));
+        }
+    }
+
+    v.object("location", true, serde_fields::<LatLon>());
+    v.number_in("location.lat", true, -90.0, 90.0);
+    v.number_in("location.lon", true, -180.0, 180.0);
+
+    v.timezone("timezone", true);
+    v.time_format("time_format", true);
+    v.schedule("schedule", false);
+    v.integer_in("max_image_age_minutes", false, 1, 10080);
+
+    if v.object("knowyourmeme", false, serde_fields::<knowyourmeme::ClientOptions>()) {
+        v.url("knowyourmeme.base_url", false);
+        v.string("knowyourmeme.user_agent", false);
+        v.integer_in("knowyourmeme.timeout_seconds", false, 1, 600);
+        v.integer_in("knowyourmeme.connect_timeout_seconds", false, 1, 600);
+    }
+
+    if v.object("meme_selection", false, serde_fields::<MemeSelection>()) {
+        v.one_of("meme_selection.policy", false, serde_variants::<SelectionPolicy>());
+        v.integer_in("meme_selection.rotate_hours", false, 1, 720);
+        v.integer_in("meme_selection.repeat_after_days", false, 0, 365);
+        v.string("meme_selection.history_path", false);
+    }
+    if v.object("meme_selection.feed", false, serde_fields::<knowyourmeme::FeedOptions>()) {
+        let entry_types = serde_variants::<EntryType>();
+        v.integer_in("meme_selection.feed.pages", false, 1, 10);
+        v.each_one_of("meme_selection.feed.include_types", false, entry_types);
+        v.each_one_of("meme_selection.feed.exclude_types", false, entry_types);
+        v.strings("meme_selection.feed.tags", false);
+        v.strings("meme_selection.feed.exclude_tags", false);
+    }
+    if v.object("meme_selection.feed.safety", false, serde_fields::<SafetyFilter>()) {
+        v.boolean("meme_selection.feed.safety.allow_nsfw", false);
+        v.strings("meme_selection.feed.safety.blocked_keywords", false);
+        v.strings("meme_selection.feed.safety.allowed_keywords", false);
+    }
+
+    // The known settings depend on the provider
+    let has_content = v.field("content", false).is_some();
+    let provider_keys = |fields: &[&'static str]| [&["provider"], fields].concat();
+    match v.one_of("content.provider", has_content, serde_tag_values::<ContentOptions>("provider")) {
+        Some("quotes") => {
+            if v.object("content", false, &provider_keys(serde_fields::<QuoteOptions>())) {
+                v.string("content.path", true);
+                v.string("content.title", false);
+            }
+        }
+        Some("feed") => {
+            if v.object("content", false, &provider_keys(serde_fields::<RssFeedOptions>())) {
+                v.string("content.url", true);
+                validate_dithering(&mut v, "content.dithering");
+            }
+        }
+        Some("photos") => {
+            if v.object("content", false, &provider_keys(serde_fields::<PhotoOptions>())) {
+                v.string("content.path", true);
+                v.boolean("content.shuffle", false);
+                v.integer_in("content.rotate_hours", false, 1, 720);
+                v.one_of("content.fit", false, serde_variants::<ImageFit>());
+                validate_dithering(&mut v, "content.dithering");
+            }
+        }
+        _ => {
+            v.object("content", false, &["provider"]);
+        }
+    }
+
+    if v.object("images", false, serde_fields::<ImageOptions>()) {
+        v.string("images.cache_dir", false);
+        v.integer_in("images.cache_hours", false, 0, 8760);
+        v.integer_in("images.timeout_seconds", false, 1, 600);
+        v.integer_in("images.max_size_mb", false, 1, 100);
+    }
+
+    for (path, names) in [("weekday_names", serde_fields::<WeekdayNames>()), ("weather_conditions", serde_fields::<WeatherConditions>())] {
+        v.object(path, true, names);
+        for name in names {
+            v.string(&join_path(path, name), true);
+        }
+    }
+
+    validate_dithering(&mut v, "meme_dithering");
+
+    v.finish()
+}
+
+fn validate_dithering(v: &mut Validator, path: &str) {
+    if v.object(path, false, serde_fields::<DitherOptions>()) {
+        v.one_of(&join_path(path, "algorithm"), false, serde_variants::<DitherAlgorithm>());
+        validate_palette(v, &join_path(path, "palette"));
+        v.number_in(&join_path(path, "gamma"), false, 0.1, 10.0);
+        v.number_in(&join_path(path, "contrast"), false, 0.0, 10.0);
+    }
+}
+
+/// Palettes are either a preset name or a list of hex colors
+fn validate_palette(v: &mut Validator, path: &str) {
+    match v.field(path, false) {
+        None | Some(Value::String(_)) => {
+            v.one_of(path, false, PALETTES);
+        }
+        Some(Value::Array(colors)) => {
+            if colors.is_empty() {
+                v.issue(path, "Palette has no colors", Some("Add at least one color like \"#000000\"".to_string()));
+            }
+            for (i, color) in colors.iter().enumerate() {
+                if color.as_str().and_then(parse_hex_color).is_none() {
+                    v.issue(&format!("{}[{}]", path, i), format!("Invalid color {}", color), Some("Use a hex color like \"#ff0000\"".to_string()));
+                }
+            }
+        }
+        Some(_) => v.issue(path, "Expected a palette name or a list of colors", Some(format!("Use one of: {}", PALETTES.join(", ")))),
+    }
 }
 
 include!(concat!(env!("OUT_DIR"), "/config.rs"));
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use serde_json::json;
+    use crate::content::ImageFit;
+    use crate::dithering::DitherAlgorithm;
+    use super::*;
+
+    fn fixture_json() -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/config.json");
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn fixture_config() -> Value {
+        serde_json::from_str(&fixture_json()).unwrap()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        assert_eq!(validate(&fixture_config()), Ok(()));
+        assert!(Config::parse(&fixture_json(), ConfigFormat::Json5, &Environment::default()).is_ok());
+    }
+
+    /// The fixture config with the top-level keys of `patch` replaced
+    fn patched(patch: Value) -> Value {
+        let mut config = fixture_config();
+        for (key, value) in patch.as_object().unwrap() {
+            config[key] = value.clone();
+        }
+        config
+    }
+
+    fn parse_patched(patch: Value) -> Config {
+        Config::parse(&patched(patch).to_string(), ConfigFormat::Json5, &Environment::default()).unwrap()
+    }
+
+    /// The paths of all issues of the patched fixture config
+    fn issues_for(patch: Value) -> Vec<String> {
+        issue_paths(&patched(patch))
+    }
+
+    fn issue_paths(config: &Value) -> Vec<String> {
+        let ValidationError(issues) = validate(config).unwrap_err();
+        issues.into_iter().map(|issue| issue.path).collect()
+    }
+
+    #[test]
+    fn test_meme_feed_options() {
+        let config = parse_patched(json!({ "meme_selection": { "feed": { "pages": 2, "include_types": ["meme", "video"], "tags": ["cat"] } } }));
+        assert_eq!(config.meme_selection.feed.pages, 2);
+        assert_eq!(config.meme_selection.feed.include_types, [knowyourmeme::EntryType::Meme, knowyourmeme::EntryType::Video]);
+        // Defaults of the other fields are kept
+        assert_eq!(config.meme_selection.feed.exclude_types, [knowyourmeme::EntryType::Editorial]);
+
+        assert_eq!(
+            issues_for(json!({ "meme_selection": { "feed": { "include_types": ["memes"], "tags": "cat" } } })),
+            ["meme_selection.feed.include_types[0]", "meme_selection.feed.tags"]
+        );
+    }
+
+    #[test]
+    fn test_meme_safety_options() {
+        let config = parse_patched(json!({ "meme_selection": { "feed": { "safety": { "blocked_keywords": ["gore"] } } } }));
+        assert_eq!(config.meme_selection.feed.safety.blocked_keywords, ["gore"]);
+        assert!(!config.meme_selection.feed.safety.allow_nsfw);
+
+        assert_eq!(
+            issues_for(json!({ "meme_selection": { "feed": { "safety": { "allow_nsfw": "no", "allowed_keywords": [1] } } } })),
+            ["meme_selection.feed.safety.allow_nsfw", "meme_selection.feed.safety.allowed_keywords[0]"]
+        );
+    }
+
+    #[test]
+    fn test_content_options() {
+        assert_eq!(parse_patched(json!({})).content, ContentOptions::Knowyourmeme);
+
+        let ContentOptions::Quotes(options) = parse_patched(json!({ "content": { "provider": "quotes", "path": "quotes.txt" } })).content else {
+            panic!("Expected quotes");
+        };
+        assert_eq!(options.path, PathBuf::from("quotes.txt"));
+        assert_eq!(options.title, "Quote of the day");
+
+        assert_eq!(issues_for(json!({ "content": { "provider": "quotes", "title": "Quote" } })), ["content.path"]);
+        // Settings of other providers are reported
+        assert_eq!(issues_for(json!({ "content": { "provider": "knowyourmeme", "path": "quotes.txt" } })), ["content.path"]);
+
+        let config = parse_patched(json!({ "content": { "provider": "feed", "url": "https://example.com/rss.xml" } }));
+        assert!(matches!(config.content, ContentOptions::Feed(options) if options.url == "https://example.com/rss.xml"));
+
+        let ContentOptions::Photos(options) = parse_patched(json!({
+            "content": { "provider": "photos", "path": "/photos", "shuffle": true, "fit": "contain", "dithering": { "algorithm": "atkinson" } }
+        })).content else {
+            panic!("Expected photos");
+        };
+        assert!(options.shuffle);
+        assert_eq!(options.rotate_hours, 1);
+        assert_eq!(options.fit, ImageFit::Contain);
+        assert_eq!(options.dithering.map(|dithering| dithering.algorithm), Some(DitherAlgorithm::Atkinson));
+
+        assert_eq!(
+            issues_for(json!({ "content": { "provider": "photos", "path": "/photos", "rotate_hours": 0, "fit": "fill", "dithering": { "algorithm": "dots" } } })),
+            ["content.rotate_hours", "content.fit", "content.dithering.algorithm"]
+        );
+
+        // Hours are whole numbers
+        let ValidationError(issues) = validate(&patched(json!({ "content": { "provider": "photos", "path": "/photos", "rotate_hours": 1.5 } }))).unwrap_err();
+        assert_eq!(issues[0].path, "content.rotate_hours");
+        assert_eq!(issues[0].message, "1.5 isn't a whole number");
+
+        assert_eq!(issues_for(json!({ "content": { "provider": "rss" } })), ["content.provider"]);
+    }
+
+    #[test]
+    fn test_image_options() {
+        let config = parse_patched(json!({ "images": { "cache_dir": "/var/cache/renderer", "max_size_mb": 5 } }));
+        assert_eq!(config.images.cache_dir, Some(PathBuf::from("/var/cache/renderer")));
+        assert_eq!(config.images.max_size_mb, 5);
+        assert_eq!(config.images.timeout_seconds, ImageOptions::default().timeout_seconds);
+
+        assert_eq!(issues_for(json!({ "images": { "timeout_seconds": 0, "max_size": 5 } })), ["images.max_size", "images.timeout_seconds"]);
+    }
+
+    #[test]
+    fn test_knowyourmeme_options() {
+        let config = parse_patched(json!({ "knowyourmeme": { "base_url": "http://mirror.local/kym/", "timeout_seconds": 5 } }));
+        assert_eq!(config.knowyourmeme.base_url, "http://mirror.local/kym/");
+        assert_eq!(config.knowyourmeme.timeout_seconds, 5);
+        assert_eq!(config.knowyourmeme.connect_timeout_seconds, knowyourmeme::ClientOptions::default().connect_timeout_seconds);
+
+        assert_eq!(issues_for(json!({ "knowyourmeme": { "base_url": "mirror.local", "timeout_secs": 5 } })), ["knowyourmeme.timeout_secs", "knowyourmeme.base_url"]);
+    }
+
+    #[test]
+    fn test_example_config() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("example-config.json");
+        let text = fs::read_to_string(&path).unwrap();
+        Config::parse(&text, ConfigFormat::from_path(&path).unwrap(), &Environment::default()).unwrap();
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let error = Config::parse("{\n  \"timezone\": @\n}", ConfigFormat::Json5, &Environment::default()).unwrap_err();
+        assert_eq!(format!("{:#}", error), "Failed to parse config: line 2, column 15: expected array, boolean, null, number, object, or string");
+    }
+
+    #[test]
+    fn test_shared_config() {
+        let mut renderer = fixture_config();
+        let timezone = renderer.as_object_mut().unwrap().remove("timezone").unwrap();
+        let shared = json!({
+            "timezone": timezone,
+            "renderer": renderer,
+            "esp": { "wifi": { "ssid": "Wifi", "psk": "secret" } }
+        });
+
+        let config = Config::parse(&shared.to_string(), ConfigFormat::Json5, &Environment::default()).unwrap();
+        assert_eq!(config.timezone, chrono_tz::Europe::Berlin);
+        assert_eq!(config.image_name, "esp_image.img");
+    }
+
+    #[test]
+    fn test_environment_overrides() {
+        let json = fixture_json().replace("\"image_name\": \"esp_image.img\"", "\"image_name\": \"${IMAGE_NAME}\"");
+        let environment = Environment::from_vars([
+            ("IMAGE_NAME", "display.img"),
+            ("RENDERER__WEBDAV_AUTH__USERNAME", "user"),
+            ("RENDERER__WEBDAV_AUTH__PASSWORD", "secret"),
+            ("RENDERER__LOCATION__LAT", "48.1"),
+        ]);
+        let config = Config::parse(&json, ConfigFormat::Json5, &environment).unwrap();
+
+        assert_eq!(config.image_name, "display.img");
+        let auth = config.webdav_auth.unwrap();
+        assert_eq!((auth.username.as_str(), auth.password.as_str()), ("user", "secret"));
+        assert_eq!(config.location.lat, 48.1);
+    }
+
+    #[test]
+    fn test_reports_all_problems() {
+        let mut config = fixture_config();
+        config["timezone"] = "Europe/Berln".into();
+        config["time_format"] = "%d %Q".into();
+        config["weekday_names"].as_object_mut().unwrap().remove("sunday");
+        config["weather_condition"] = json!({});
+        config["meme_dithering"]["palette"] = json!(["#000000", "red"]);
+        config["schedule"] = json!({ "cron": "0 25 * * *" });
+
+        assert_eq!(issue_paths(&config), [
+            "weather_condition",
+            "timezone",
+            "time_format",
+            "schedule",
+            "weekday_names.sunday",
+            "meme_dithering.palette[1]",
+        ]);
+        let ValidationError(issues) = validate(&config).unwrap_err();
+        assert_eq!(issues[0].suggestion.as_deref(), Some("Did you mean \"weather_conditions\"?"));
+    }
+}
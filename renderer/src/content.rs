@@ -0,0 +1,102 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::config::Config;
+use crate::dithering::DitherOptions;
+use crate::feed_provider::{FeedProvider, RssFeedOptions};
+use crate::meme_provider::MemeProvider;
+use crate::photo_provider::{PhotoOptions, PhotoProvider};
+use crate::quote_provider::{QuoteOptions, QuoteProvider};
+
+/// What the lower panel shows, independent of where it comes from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContentItem {
+    /// Identifies the item across renders, e.g. the URL of an article
+    pub id: String,
+    pub title: String,
+    /// Short line under the title, e.g. the name of a meme
+    pub subtitle: Option<String>,
+    /// Smaller line under the subtitle, e.g. "Origin: 2019 · Status: Confirmed"
+    pub details: Option<String>,
+    pub body: String,
+    /// http(s) or file URL of the image left of the text. Without an image, the text takes the whole width.
+    /// Without any text, the image takes the whole panel.
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub image_fit: ImageFit,
+    /// How the image is dithered instead of `meme_dithering`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dithering: Option<DitherOptions>,
+}
+
+/// How the image is fit into its box
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageFit {
+    /// Scale to the size of the box, even if it's distorted
+    #[default]
+    Stretch,
+    /// Fill the box and crop what doesn't fit
+    Cover,
+    /// Show the whole image with white bars
+    Contain,
+}
+
+impl ContentItem {
+    /// Whether the item only consists of an image
+    pub fn is_image_only(&self) -> bool {
+        self.title.is_empty() && self.subtitle.is_none() && self.details.is_none() && self.body.is_empty()
+    }
+}
+
+/// Source of the items in the lower panel
+pub trait ContentProvider {
+    /// Name for logs, e.g. `knowyourmeme`
+    fn name(&self) -> &'static str;
+
+    /// Choose the item for the next image. `None` leaves the panel empty.
+    fn next_item(&mut self, now: DateTime<Utc>) -> Result<Option<ContentItem>>;
+
+    /// Item that is on the display. It's shown again if `next_item` fails.
+    fn current_item(&self) -> Option<ContentItem> {
+        None
+    }
+
+    /// Called after an image with the item was rendered
+    fn shown(&mut self, _item: &ContentItem, _now: DateTime<Utc>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Which provider fills the lower panel. Its settings are next to `provider`.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(tag = "provider", rename_all = "kebab-case")]
+pub enum ContentOptions {
+    /// Articles of the Know Your Meme front page, set up with `knowyourmeme` and `meme_selection`
+    #[default]
+    Knowyourmeme,
+    /// A quote of the day from a text file
+    Quotes(QuoteOptions),
+    /// The newest entry of an RSS or Atom feed
+    Feed(RssFeedOptions),
+    /// A slideshow of the photos in a local folder
+    Photos(PhotoOptions),
+}
+
+/// Whether the URL is http(s). Images of remote sources must use these,
+/// so that a feed or a scraped page can't make the renderer read local files.
+pub fn is_web_url(url: &Url) -> bool {
+    url.scheme() == "http" || url.scheme() == "https"
+}
+
+/// Create the provider that is selected in the config
+pub fn provider(config: &Config) -> Result<Box<dyn ContentProvider>> {
+    let provider: Box<dyn ContentProvider> = match &config.content {
+        ContentOptions::Knowyourmeme => Box::new(MemeProvider::new(config)?),
+        ContentOptions::Quotes(options) => Box::new(QuoteProvider::new(options, config.timezone)),
+        ContentOptions::Feed(options) => Box::new(FeedProvider::new(options)?),
+        ContentOptions::Photos(options) => Box::new(PhotoProvider::new(options, config.timezone)),
+    };
+    Ok(provider)
+}
@@ -1,40 +1,376 @@
-use image::{Rgb, Luma, DynamicImage, ImageBuffer};
-use image::imageops::colorops;
+use std::borrow::Cow;
+use image::{Rgb, RgbImage, DynamicImage};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-struct SimpleColorMap;
+/// Colors an image gets reduced to before it is drawn on the display
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Cow<'static, [Rgb<u8>]>
+}
+
+impl Palette {
+    pub const BLACK_WHITE: Palette = Palette::from_static(&[
+        Rgb([0, 0, 0]),
+        Rgb([255, 255, 255]),
+    ]);
+
+    pub const BLACK_WHITE_RED: Palette = Palette::from_static(&[
+        Rgb([0, 0, 0]),
+        Rgb([255, 255, 255]),
+        Rgb([255, 0, 0]),
+    ]);
+
+    pub const fn from_static(colors: &'static [Rgb<u8>]) -> Self {
+        Palette { colors: Cow::Borrowed(colors) }
+    }
+
+    pub fn new(colors: Vec<Rgb<u8>>) -> Self {
+        assert!(!colors.is_empty(), "Palette needs at least one color");
+        Palette { colors: Cow::Owned(colors) }
+    }
+
+    /// Get the palette color closest to the given color.
+    /// Channels are weighted by their perceived brightness, see [`brightness`].
+    fn nearest(&self, color: [f32; 3]) -> Rgb<u8> {
+        let distance = |c: &Rgb<u8>| {
+            let dr = color[0] - c[0] as f32;
+            let dg = color[1] - c[1] as f32;
+            let db = color[2] - c[2] as f32;
+            0.30 * dr * dr + 0.59 * dg * dg + 0.11 * db * db
+        };
+
+        *self.colors.iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .expect("Palette is empty")
+    }
+
+    /// Largest brightness step between the palette colors that grays are mapped to.
+    /// Used as the strength of ordered dithering, so that every gray between two of these colors gets dithered.
+    /// In `black-white-red` that's the whole step from black to white, because grays never map to red.
+    fn spread(&self) -> f32 {
+        let mut levels: Vec<f32> = (0..=255u8)
+            .map(|v| brightness(&self.nearest([v as f32; 3])))
+            .collect();
+        levels.sort_by(f32::total_cmp);
+        levels.dedup();
+
+        levels.windows(2).map(|pair| pair[1] - pair[0]).fold(0.0, f32::max)
+    }
+}
+
+/// Perceived brightness of the color in the range 0..255
+fn brightness(color: &Rgb<u8>) -> f32 {
+    0.30 * color[0] as f32 + 0.59 * color[1] as f32 + 0.11 * color[2] as f32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DitherAlgorithm {
+    /// Map every pixel to the nearest palette color without dithering
+    Threshold,
+    FloydSteinberg,
+    /// Only diffuses 3/4 of the error which gives more contrast but loses detail in dark and bright areas
+    Atkinson,
+    Sierra,
+    /// Ordered dithering with an 8x8 Bayer matrix
+    Bayer,
+    /// Ordered dithering with interleaved gradient noise, which looks less regular than Bayer.
+    /// `blue-noise` is accepted as an alias.
+    #[serde(alias = "blue-noise")]
+    InterleavedGradientNoise,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DitherOptions {
+    pub algorithm: DitherAlgorithm,
+    pub palette: Palette,
+    /// Values above 1.0 brighten the midtones, values below 1.0 darken them
+    pub gamma: f32,
+    /// Values above 1.0 increase the contrast, values below 1.0 decrease it
+    pub contrast: f32,
+}
+
+impl Default for DitherOptions {
+    fn default() -> Self {
+        DitherOptions {
+            algorithm: DitherAlgorithm::FloydSteinberg,
+            palette: Palette::BLACK_WHITE,
+            gamma: 1.0,
+            contrast: 1.0,
+        }
+    }
+}
+
+/// Palettes are configured by preset name (`black-white`, `black-white-red`)
+/// or as a list of hex colors like `["#000000", "#ffffff"]`
+impl<'de> Deserialize<'de> for Palette {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum PaletteConfig {
+            Preset(String),
+            Colors(Vec<String>),
+        }
+
+        match PaletteConfig::deserialize(deserializer)? {
+            PaletteConfig::Preset(name) => match name.as_str() {
+                "black-white" => Ok(Palette::BLACK_WHITE),
+                "black-white-red" => Ok(Palette::BLACK_WHITE_RED),
+                _ => Err(de::Error::custom(format!("unknown palette {:?}, expected black-white, black-white-red or a list of hex colors", name))),
+            },
+            PaletteConfig::Colors(colors) => {
+                if colors.is_empty() {
+                    return Err(de::Error::custom("palette needs at least one color"));
+                }
+                let colors = colors.iter()
+                    .map(|c| parse_hex_color(c).ok_or_else(|| de::Error::custom(format!("invalid color {:?}, expected #rrggbb", c))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Palette::new(colors))
+            }
+        }
+    }
+}
+
+/// Presets are written by name, other palettes as a list of hex colors
+impl Serialize for Palette {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if *self == Palette::BLACK_WHITE {
+            return serializer.serialize_str("black-white");
+        }
+        if *self == Palette::BLACK_WHITE_RED {
+            return serializer.serialize_str("black-white-red");
+        }
+        serializer.collect_seq(self.colors.iter().map(|c| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])))
+    }
+}
+
+/// Parse a color like `#ff0000`
+pub fn parse_hex_color(text: &str) -> Option<Rgb<u8>> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+/// Error diffusion kernels as (dx, dy, weight)
+const FLOYD_STEINBERG: &[(i32, i32, f32)] = &[
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0), (0, 1, 5.0 / 16.0), (1, 1, 1.0 / 16.0),
+];
+
+const ATKINSON: &[(i32, i32, f32)] = &[
+    (1, 0, 1.0 / 8.0), (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0), (0, 1, 1.0 / 8.0), (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+
+const SIERRA: &[(i32, i32, f32)] = &[
+    (1, 0, 5.0 / 32.0), (2, 0, 3.0 / 32.0),
+    (-2, 1, 2.0 / 32.0), (-1, 1, 4.0 / 32.0), (0, 1, 5.0 / 32.0), (1, 1, 4.0 / 32.0), (2, 1, 2.0 / 32.0),
+    (-1, 2, 2.0 / 32.0), (0, 2, 3.0 / 32.0), (1, 2, 2.0 / 32.0),
+];
 
-impl colorops::ColorMap for SimpleColorMap {
-    type Color = Luma<u8>;
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
 
-    fn index_of(&self, pixel: &Self::Color) -> usize {
-        if pixel[0] < 128 {
-            0
-        } else {
-            1
+/// Reduce the image to the colors of the palette using the given options.
+/// The resulting image only contains palette colors.
+pub fn dither_image(image: &DynamicImage, options: &DitherOptions) -> RgbImage {
+    let mut image = image.to_rgb8();
+    adjust_levels(&mut image, options.gamma, options.contrast);
+
+    match options.algorithm {
+        DitherAlgorithm::Threshold => ordered_dither(&mut image, &options.palette, |_, _| 0.5),
+        DitherAlgorithm::FloydSteinberg => diffuse_error(&mut image, &options.palette, FLOYD_STEINBERG),
+        DitherAlgorithm::Atkinson => diffuse_error(&mut image, &options.palette, ATKINSON),
+        DitherAlgorithm::Sierra => diffuse_error(&mut image, &options.palette, SIERRA),
+        DitherAlgorithm::Bayer => ordered_dither(&mut image, &options.palette, bayer_threshold),
+        DitherAlgorithm::InterleavedGradientNoise => ordered_dither(&mut image, &options.palette, interleaved_gradient_noise_threshold),
+    }
+
+    image
+}
+
+/// Apply gamma and contrast correction to every channel
+fn adjust_levels(image: &mut RgbImage, gamma: f32, contrast: f32) {
+    if gamma == 1.0 && contrast == 1.0 {
+        return;
+    }
+
+    // Precalculate the mapping for every possible channel value
+    let lut: Vec<u8> = (0..=255u8).map(|v| {
+        let v = (v as f32 / 255.0).powf(1.0 / gamma);
+        let v = (v - 0.5) * contrast + 0.5;
+        (v * 255.0).round().clamp(0.0, 255.0) as u8
+    }).collect();
+
+    for pixel in image.pixels_mut() {
+        for c in pixel.0.iter_mut() {
+            *c = lut[*c as usize];
         }
     }
+}
+
+fn diffuse_error(image: &mut RgbImage, palette: &Palette, kernel: &[(i32, i32, f32)]) {
+    let (width, height) = (image.width() as i32, image.height() as i32);
+
+    // Work on floats so that the accumulated error doesn't get clipped
+    let mut buffer: Vec<[f32; 3]> = image.pixels()
+        .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32])
+        .collect();
 
-    fn map_color(&self, pixel: &mut Self::Color) {
-        let index = self.index_of(pixel);
-        if index == 0 {
-            pixel.0 = [0];  // Fully black
-        } else {
-            pixel.0 = [255];  // Fully white
+    for y in 0..height {
+        for x in 0..width {
+            let index = (y * width + x) as usize;
+            let old = buffer[index];
+            let new = palette.nearest(old);
+            image.put_pixel(x as u32, y as u32, new);
+
+            let error = [old[0] - new[0] as f32, old[1] - new[1] as f32, old[2] - new[2] as f32];
+
+            for &(dx, dy, weight) in kernel {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || nx >= width || ny >= height {
+                    continue;
+                }
+
+                let neighbour = &mut buffer[(ny * width + nx) as usize];
+                for c in 0..3 {
+                    neighbour[c] += error[c] * weight;
+                }
+            }
         }
     }
 }
 
-pub fn dither_image_grayscale(image: DynamicImage) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    // Convert the image to grayscale
-    let mut grayscale_img = image.to_luma8();
+/// Dither with a threshold map that returns values in the range 0..1 for each pixel
+fn ordered_dither(image: &mut RgbImage, palette: &Palette, threshold: impl Fn(u32, u32) -> f32) {
+    let spread = palette.spread();
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let offset = (threshold(x, y) - 0.5) * spread;
+        let color = [pixel[0] as f32 + offset, pixel[1] as f32 + offset, pixel[2] as f32 + offset];
+        *pixel = palette.nearest(color);
+    }
+}
+
+fn bayer_threshold(x: u32, y: u32) -> f32 {
+    (BAYER_8X8[(y % 8) as usize][(x % 8) as usize] as f32 + 0.5) / 64.0
+}
+
+/// Interleaved gradient noise by Jorge Jimenez
+fn interleaved_gradient_noise_threshold(x: u32, y: u32) -> f32 {
+    let v = 0.067_110_56 * x as f32 + 0.005_837_15 * y as f32;
+    (52.982_918 * v.fract()).fract()
+}
 
-    // Perform dithering
-    colorops::dither(&mut grayscale_img, &SimpleColorMap);
+#[cfg(test)]
+mod tests {
+    use image::Rgb32FImage;
+    use super::*;
 
-	let rgb_img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(grayscale_img.width(), grayscale_img.height(), |x, y| {
-		let pixel = grayscale_img.get_pixel(x, y);
-		Rgb([pixel[0], pixel[0], pixel[0]])
-	});
+    const ALGORITHMS: &[DitherAlgorithm] = &[
+        DitherAlgorithm::Threshold,
+        DitherAlgorithm::FloydSteinberg,
+        DitherAlgorithm::Atkinson,
+        DitherAlgorithm::Sierra,
+        DitherAlgorithm::Bayer,
+        DitherAlgorithm::InterleavedGradientNoise,
+    ];
 
-	rgb_img
-}
\ No newline at end of file
+    /// Gray from left to right, more red from top to bottom
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(64, 32, |x, y| {
+            let gray = x as f32 / 63.0;
+            Rgb([gray + (1.0 - gray) * y as f32 / 31.0, gray, gray])
+        }))
+    }
+
+    fn options(algorithm: DitherAlgorithm, palette: Palette) -> DitherOptions {
+        DitherOptions { algorithm, palette, ..DitherOptions::default() }
+    }
+
+    #[test]
+    fn test_only_palette_colors() {
+        for palette in [Palette::BLACK_WHITE, Palette::BLACK_WHITE_RED] {
+            for &algorithm in ALGORITHMS {
+                let image = dither_image(&gradient(), &options(algorithm, palette.clone()));
+                assert_eq!(image.dimensions(), (64, 32));
+                let mut used: Vec<Rgb<u8>> = image.pixels().copied().collect();
+                used.sort_by_key(|c| c.0);
+                used.dedup();
+                assert!(used.iter().all(|c| palette.colors.contains(c)), "{:?} {:?}: {:?}", algorithm, palette, used);
+                assert_eq!(used.len(), palette.colors.len(), "{:?} {:?}", algorithm, palette);
+            }
+        }
+    }
+
+    #[test]
+    fn test_keeps_brightness() {
+        // Dark and light grays must be dithered as well, not only the midtones.
+        // Atkinson is left out because it loses dark and bright details on purpose.
+        for level in [40u8, 128, 215] {
+            let gray = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([level; 3])));
+            for &algorithm in ALGORITHMS.iter().filter(|a| !matches!(a, DitherAlgorithm::Threshold | DitherAlgorithm::Atkinson)) {
+                let image = dither_image(&gray, &options(algorithm, Palette::BLACK_WHITE_RED));
+                assert!(image.pixels().all(|c| *c != Rgb([255, 0, 0])), "{:?} dithered gray {} with red", algorithm, level);
+                let mean = image.pixels().map(|c| c[0] as f32).sum::<f32>() / 1024.0;
+                assert!((mean - level as f32).abs() < 20.0, "{:?} turned gray {} into {}", algorithm, level, mean);
+            }
+        }
+    }
+
+    #[test]
+    fn test_palettes() {
+        assert_eq!(Palette::BLACK_WHITE.spread(), 255.0);
+        assert_eq!(Palette::BLACK_WHITE_RED.spread(), 255.0);
+        let grays = Palette::new(vec![Rgb([0, 0, 0]), Rgb([85, 85, 85]), Rgb([170, 170, 170]), Rgb([255, 255, 255])]);
+        assert!((grays.spread() - 85.0).abs() < 0.01);
+
+        assert_eq!(Palette::BLACK_WHITE_RED.nearest([100.0; 3]), Rgb([0, 0, 0]));
+        assert_eq!(Palette::BLACK_WHITE_RED.nearest([160.0; 3]), Rgb([255, 255, 255]));
+        assert_eq!(Palette::BLACK_WHITE_RED.nearest([200.0, 40.0, 30.0]), Rgb([255, 0, 0]));
+
+        let parse = |json: &str| serde_json::from_str::<Palette>(json);
+        assert_eq!(parse("\"black-white-red\"").unwrap(), Palette::BLACK_WHITE_RED);
+        assert_eq!(parse("[\"#000000\", \"#FF0000\"]").unwrap(), Palette::new(vec![Rgb([0, 0, 0]), Rgb([255, 0, 0])]));
+        assert!(parse("\"purple\"").is_err());
+        assert!(parse("[]").is_err());
+        assert!(parse("[\"red\"]").is_err());
+
+        assert_eq!(serde_json::to_string(&Palette::BLACK_WHITE).unwrap(), "\"black-white\"");
+        assert_eq!(serde_json::to_string(&grays).unwrap(), "[\"#000000\",\"#555555\",\"#aaaaaa\",\"#ffffff\"]");
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let total = |kernel: &[(i32, i32, f32)]| kernel.iter().map(|(_, _, weight)| weight).sum::<f32>();
+        assert!((total(FLOYD_STEINBERG) - 1.0).abs() < 1e-6);
+        assert!((total(SIERRA) - 1.0).abs() < 1e-6);
+        assert!((total(ATKINSON) - 0.75).abs() < 1e-6);
+
+        let mut bayer: Vec<f32> = (0..8).flat_map(|y| (0..8).map(move |x| bayer_threshold(x, y))).collect();
+        assert!(bayer.iter().all(|t| *t > 0.0 && *t < 1.0));
+        bayer.sort_by(f32::total_cmp);
+        bayer.dedup();
+        assert_eq!(bayer.len(), 64);
+        assert_eq!(bayer_threshold(3, 5), bayer_threshold(11, 13));
+
+        assert!((0..64).flat_map(|y| (0..64).map(move |x| interleaved_gradient_noise_threshold(x, y))).all(|t| (0.0..1.0).contains(&t)));
+
+        let algorithm = |json: &str| serde_json::from_str::<DitherAlgorithm>(json).unwrap();
+        assert_eq!(algorithm("\"blue-noise\""), DitherAlgorithm::InterleavedGradientNoise);
+        assert_eq!(algorithm("\"interleaved-gradient-noise\""), DitherAlgorithm::InterleavedGradientNoise);
+    }
+}
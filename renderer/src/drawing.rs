@@ -1,5 +1,6 @@
 use chrono::prelude::*;
-use reqwest::blocking::Client;
+use tracing::warn;
+use image::DynamicImage;
 use image::imageops::FilterType;
 
 use imageproc::drawing::{Canvas, draw_line_segment_mut, BresenhamLineIter};
@@ -8,16 +9,21 @@ use rusttype::{Font, Scale};
 use epd_waveshare::epd7in5b_v2::{WIDTH as EPD_WIDTH, HEIGHT as EPD_HEIGHT};
 
 use crate::text::{draw_text_mut, measure_text, draw_text_wrapped, adjust_scale_to_fit_box, adjust_scale_to_fit_lines};
-use crate::weather::WeatherData;
+use crate::DisplayData;
+use crate::content::{ContentItem, ImageFit};
 use crate::dithering::*;
-use crate::config::CONFIG;
+use crate::images::{placeholder, ImageLoader};
+use crate::config::Config;
 
 const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
 const RED: Rgb<u8> = Rgb([255, 0, 0]);
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
 
 /// Draw weather graph with temperature and rain
+#[allow(clippy::too_many_arguments)]
 pub fn draw_graph(
-    weather_data: &WeatherData, 
+    display_data: &DisplayData,
+    config: &Config,
     min_temp_scale: i32, 
     max_temp_scale: i32, 
     max_rain_scale: i32, 
@@ -30,9 +36,9 @@ pub fn draw_graph(
     let height = height as f32;
     let width = width as f32;
 
-    let date_time: DateTime<FixedOffset> = chrono::Local::now().into();
+    let date_time = display_data.date_time;
 
-    let forecast_data = &weather_data.weather_forecast;
+    let forecast_data = &display_data.weather.weather_forecast;
     let horizontal_spacing = (width-1.0) / (forecast_data.len()-1) as f32;
 
     // Convert rain probabilities 0-100 into pixel heights.
@@ -77,13 +83,13 @@ pub fn draw_graph(
         // Draw weekday
         if d1.day() != d2.day() || i == 0 {
             let text = match d2.weekday() {
-                Weekday::Mon => &CONFIG.weekday_names.monday,
-                Weekday::Tue => &CONFIG.weekday_names.tuesday,
-                Weekday::Wed => &CONFIG.weekday_names.wednesday,
-                Weekday::Thu => &CONFIG.weekday_names.thursday,
-                Weekday::Fri => &CONFIG.weekday_names.friday,
-                Weekday::Sat => &CONFIG.weekday_names.saturday,
-                Weekday::Sun => &CONFIG.weekday_names.sunday,
+                Weekday::Mon => &config.weekday_names.monday,
+                Weekday::Tue => &config.weekday_names.tuesday,
+                Weekday::Wed => &config.weekday_names.wednesday,
+                Weekday::Thu => &config.weekday_names.thursday,
+                Weekday::Fri => &config.weekday_names.friday,
+                Weekday::Sat => &config.weekday_names.saturday,
+                Weekday::Sun => &config.weekday_names.sunday,
             };
             draw_text_left(&mut image, text, x + 5.0, 0.0, font, 24.0, BLACK);
         }
@@ -134,60 +140,93 @@ pub fn draw_graph(
     image
 }
 
-pub fn draw_meme(image: &mut image::ImageBuffer<Rgb<u8>, Vec<u8>>, font: &Font<'_>, article: Option<knowyourmeme::Article>, pos_y: i64) {
-    if article.is_none() {
+/// Draw the item of the lower panel with its image on the left and the text on the right
+pub fn draw_content(image: &mut image::ImageBuffer<Rgb<u8>, Vec<u8>>, font: &Font<'_>, item: Option<ContentItem>, pos_y: i64, dither_options: &DitherOptions, images: &ImageLoader) {
+    let Some(item) = item else {
         return;
-    }
+    };
 
     const PADDING: i64 = 15;
 
     let pos_y = pos_y + PADDING;
+    let content_height = (EPD_HEIGHT as i64 - pos_y - PADDING) as u32;
+
+    // Image on the left. Without an image the text starts at the padding. Without text the image takes the whole width.
+    let mut text_x = PADDING as f32;
+    if let Some(image_url) = &item.image_url {
+        let item_img_x = PADDING;
+        let item_img_width = match item.is_image_only() {
+            true => EPD_WIDTH - 2 * PADDING as u32,
+            false => (content_height as f32 * 1.76) as u32,
+        };
+
+        // A broken image must not stop the render
+        let item_image = match images.load(image_url) {
+            Ok(item_image) => fit_image(&item_image, item_img_width, content_height, item.image_fit),
+            Err(e) => {
+                warn!(url = %image_url, error = %format!("{:#}", e), "Couldn't load image. Showing a placeholder.");
+                placeholder(item_img_width, content_height)
+            }
+        };
 
-    let article = article.unwrap();
-
-    let article_img_x = PADDING;
-    let article_img_height = (EPD_HEIGHT as i64 - pos_y - PADDING) as u32;
-    let article_img_width = (article_img_height as f32 * 1.76) as u32;
-
-    // Download article image
-    let response = Client::new().get(article.image_url.as_str()).send().unwrap();
-    let bytes = response.bytes().unwrap();
-    let article_image = image::load_from_memory(&bytes).unwrap();
-    let article_image = article_image.resize_exact(article_img_width, article_img_height, FilterType::Nearest);
+        // Dither image. The item can choose its own dithering.
+        let item_image = dither_image(&item_image, item.dithering.as_ref().unwrap_or(dither_options));
+        image::imageops::overlay(image, &item_image, item_img_x, pos_y);
 
-    // Dither image
-    let article_image = dither_image_grayscale(article_image);
-    image::imageops::overlay(image, &article_image, article_img_x, pos_y);
+        text_x = (item_img_x + item_img_width as i64 + PADDING) as f32;
+    }
 
     // General values
-    let text_x = (article_img_x + article_img_width as i64 + PADDING) as f32;
     let text_max_width = EPD_WIDTH as f32 - text_x - PADDING as f32;
 
     // Title
-    let title_text = article.title.as_str();
+    let title_text = item.title.as_str();
     let title_y = pos_y as f32;
     let title_font_size = adjust_scale_to_fit_lines(text_max_width, 2, font, 28.0, title_text);
     let (_title_width, title_height) = draw_text_left_wrapped(image, title_text, text_x, title_y, text_max_width, 5.0, font, title_font_size, BLACK);
 
-    // Meme name
-    let meme_name_y = title_y + title_height + 5.0;
-    let mut meme_name_height = 0.0;
-    if article.meme_name.is_some() {
-        let text = article.meme_name.as_ref().unwrap().as_str();
+    // Subtitle, e.g. the meme name
+    let subtitle_y = title_y + title_height + 5.0;
+    let mut subtitle_height = 0.0;
+    if let Some(text) = &item.subtitle {
 
         let font_size = (title_font_size - 8.0).max(13.0);
 
-        let (_width, height) = draw_text_left_wrapped(image, text, text_x, meme_name_y, text_max_width, 4.0, font, font_size, BLACK);
-        meme_name_height = height + 3.0;
+        let (_width, height) = draw_text_left_wrapped(image, text, text_x, subtitle_y, text_max_width, 4.0, font, font_size, BLACK);
+        subtitle_height = height + 3.0;
     }
 
-    // Summary
-    let summary_text = article.summary.as_str();
-    let summary_y = meme_name_y + meme_name_height + 8.0;
-    let summary_max_height = article_img_height as f32 - 10.0;
-    let summary_spacing = 3.0;
-    let summary_font_size = adjust_scale_to_fit_box(text_max_width, summary_max_height, summary_spacing, font, title_font_size - 4.0, summary_text);
-    draw_text_left_wrapped(image, summary_text, text_x, summary_y, text_max_width, summary_spacing, font, summary_font_size, BLACK);
+    // Details, e.g. "Origin: YouTube, 2023 · Status: Confirmed"
+    if let Some(text) = &item.details {
+        let font_size = (title_font_size - 12.0).max(12.0);
+
+        let (_width, height) = draw_text_left_wrapped(image, text, text_x, subtitle_y + subtitle_height, text_max_width, 3.0, font, font_size, BLACK);
+        subtitle_height += height + 3.0;
+    }
+
+    // Body
+    let body_text = item.body.as_str();
+    let body_y = subtitle_y + subtitle_height + 8.0;
+    let body_max_height = content_height as f32 - 10.0;
+    let body_spacing = 3.0;
+    let body_font_size = adjust_scale_to_fit_box(text_max_width, body_max_height, body_spacing, font, title_font_size - 4.0, body_text);
+    draw_text_left_wrapped(image, body_text, text_x, body_y, text_max_width, body_spacing, font, body_font_size, BLACK);
+}
+
+/// Scale the image to exactly `width` x `height`
+fn fit_image(image: &DynamicImage, width: u32, height: u32, fit: ImageFit) -> DynamicImage {
+    match fit {
+        ImageFit::Stretch => image.resize_exact(width, height, FilterType::Triangle),
+        ImageFit::Cover => image.resize_to_fill(width, height, FilterType::Triangle),
+        ImageFit::Contain => {
+            let scaled = image.resize(width, height, FilterType::Triangle);
+            let mut boxed = DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, WHITE));
+            let x = (width - scaled.width()) / 2;
+            let y = (height - scaled.height()) / 2;
+            image::imageops::overlay(&mut boxed, &scaled, x as i64, y as i64);
+            boxed
+        }
+    }
 }
 
 // Based on draw_line_segment_mut()
@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use feed_rs::model::{Entry, Feed, MediaObject};
+use reqwest::blocking::Client;
+use scraper::Html;
+use serde::Deserialize;
+use tracing::{debug, info};
+use url::Url;
+use crate::content::{is_web_url, ContentItem, ContentProvider, ImageFit};
+use crate::dithering::DitherOptions;
+use crate::images::read_limited;
+
+/// Timeout of the request for the feed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Larger feeds are rejected, so that a broken server can't fill the memory
+const MAX_FEED_BYTES: u64 = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RssFeedOptions {
+    /// http(s) URL or path of an RSS 2.0 or Atom feed
+    pub url: String,
+    /// How the images of the entries are dithered instead of `meme_dithering`
+    #[serde(default)]
+    pub dithering: Option<DitherOptions>,
+}
+
+/// The newest entry of an RSS or Atom feed
+pub struct FeedProvider {
+    url: String,
+    dithering: Option<DitherOptions>,
+    client: Client,
+    current: Option<ContentItem>,
+}
+
+impl FeedProvider {
+    pub fn new(options: &RssFeedOptions) -> Result<Self> {
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(FeedProvider { url: options.url.clone(), dithering: options.dithering.clone(), client, current: None })
+    }
+
+    /// Download the feed or read it from a file
+    fn read_feed(&self) -> Result<Vec<u8>> {
+        let file_path = match Url::parse(&self.url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+                debug!(url = %url, "Getting feed");
+                let response = self.client.get(url.clone()).send()?.error_for_status()?;
+                if let Some(length) = response.content_length() {
+                    if length > MAX_FEED_BYTES {
+                        bail!("Feed {} has {} bytes, more than the limit of {} bytes", url, length, MAX_FEED_BYTES);
+                    }
+                }
+                // The length header might be missing or wrong
+                return read_limited(response, MAX_FEED_BYTES).with_context(|| format!("Couldn't download feed {}", url));
+            }
+            Ok(url) if url.scheme() == "file" => url.to_file_path().map_err(|_| anyhow!("Invalid file URL: {}", url))?,
+            _ => self.url.clone().into(),
+        };
+
+        File::open(&file_path).map_err(anyhow::Error::from)
+            .and_then(|file| read_limited(file, MAX_FEED_BYTES))
+            .with_context(|| format!("Couldn't read feed {}", file_path.display()))
+    }
+}
+
+impl ContentProvider for FeedProvider {
+    fn name(&self) -> &'static str {
+        "feed"
+    }
+
+    fn next_item(&mut self, _now: DateTime<Utc>) -> Result<Option<ContentItem>> {
+        let bytes = self.read_feed()?;
+        let feed = feed_rs::parser::parse(bytes.as_slice()).with_context(|| format!("Invalid feed {}", self.url))?;
+        let Some(mut item) = newest_item(&feed) else {
+            bail!("No entries with a title in feed {}", self.url);
+        };
+        item.dithering = self.dithering.clone();
+        info!(id = %item.id, "Selected feed entry");
+
+        self.current = Some(item.clone());
+        Ok(Some(item))
+    }
+
+    fn current_item(&self) -> Option<ContentItem> {
+        self.current.clone()
+    }
+}
+
+/// Show the newest entry with a title. Entries without a date count as older than dated ones.
+fn newest_item(feed: &Feed) -> Option<ContentItem> {
+    let entries = feed.entries.iter().filter(|entry| entry.title.is_some());
+    // `max_by_key` returns the last of equal entries, but feeds usually list the newest entry first
+    let entry = entries.rev().max_by_key(|entry| entry.published.or(entry.updated))?;
+    Some(entry_item(feed, entry))
+}
+
+fn entry_item(feed: &Feed, entry: &Entry) -> ContentItem {
+    let title = entry.title.as_ref().map(|title| strip_html(&title.content)).unwrap_or_default();
+    // Atom feeds often only have the content
+    let body = entry.summary.as_ref().map(|summary| summary.content.as_str())
+        .or_else(|| entry.content.as_ref().and_then(|content| content.body.as_deref()))
+        .map(strip_html)
+        .unwrap_or_default();
+
+    ContentItem {
+        id: entry.id.clone(),
+        title,
+        subtitle: feed.title.as_ref().map(|title| strip_html(&title.content)),
+        details: None,
+        body,
+        image_url: entry.media.iter().find_map(media_image),
+        image_fit: ImageFit::default(),
+        dithering: None,
+    }
+}
+
+/// URL of the image of an enclosure or a Media RSS object. Thumbnails of videos count as well.
+/// Only http(s) images are used, a feed must not make the renderer read local files.
+fn media_image(media: &MediaObject) -> Option<String> {
+    let content_image = media.content.iter()
+        .filter(|content| match &content.content_type {
+            Some(content_type) => content_type.ty() == "image",
+            // Media RSS doesn't require a type
+            None => true,
+        })
+        .filter_map(|content| content.url.as_ref())
+        .find(|url| is_web_url(url))
+        .map(Url::to_string);
+
+    content_image.or_else(|| media.thumbnails.iter()
+        .filter_map(|thumbnail| Url::parse(&thumbnail.image.uri).ok())
+        .find(is_web_url)
+        .map(String::from))
+}
+
+/// Text of the HTML with tags removed, entities decoded and whitespace collapsed
+fn strip_html(html: &str) -> String {
+    let fragment = Html::parse_fragment(html);
+    let text: String = fragment.root_element().text().collect();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use tiny_http::{Response, Server};
+    use super::*;
+
+    fn fixture_path(name: &str) -> String {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name).display().to_string()
+    }
+
+    fn item(name: &str) -> ContentItem {
+        let mut provider = FeedProvider::new(&RssFeedOptions { url: fixture_path(name), dithering: None }).unwrap();
+        provider.next_item(Utc::now()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_rss() {
+        let item = item("feed_rss.xml");
+        assert_eq!(item.id, "https://example.com/posts/2");
+        assert_eq!(item.title, "Cats & Dogs");
+        assert_eq!(item.subtitle.as_deref(), Some("Example News"));
+        assert_eq!(item.body, "A new study finds that cats sleep 16 hours a day.");
+        assert_eq!(item.image_url.as_deref(), Some("https://example.com/images/cats.jpg"));
+    }
+
+    #[test]
+    fn test_atom() {
+        let item = item("feed_atom.xml");
+        assert_eq!(item.id, "urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a");
+        assert_eq!(item.title, "Atom-Powered Robots Run Amok");
+        assert_eq!(item.subtitle.as_deref(), Some("Example Feed"));
+        assert_eq!(item.body, "Robots are running amok in the lab.");
+        assert_eq!(item.image_url.as_deref(), Some("https://example.org/robots-thumbnail.png"));
+    }
+
+    #[test]
+    fn test_http() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", server.server_addr().to_ip().unwrap());
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let response = match request.url() {
+                    "/rss.xml" => Response::from_data(fs::read(fixture_path("feed_rss.xml")).unwrap()),
+                    "/large.xml" => Response::from_data(vec![b' '; MAX_FEED_BYTES as usize + 1]),
+                    _ => Response::from_data(Vec::new()).with_status_code(404),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+
+        let mut provider = FeedProvider::new(&RssFeedOptions { url: format!("{}rss.xml", url), dithering: None }).unwrap();
+        assert_eq!(provider.next_item(Utc::now()).unwrap().unwrap().title, "Cats & Dogs");
+
+        let mut provider = FeedProvider::new(&RssFeedOptions { url: format!("{}missing.xml", url), dithering: None }).unwrap();
+        assert!(provider.next_item(Utc::now()).is_err());
+
+        let mut provider = FeedProvider::new(&RssFeedOptions { url: format!("{}large.xml", url), dithering: None }).unwrap();
+        let error = provider.next_item(Utc::now()).unwrap_err();
+        assert!(format!("{:#}", error).contains("limit of 5242880 bytes"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_invalid_feed() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "{ \"not\": \"a feed\" }").unwrap();
+        let mut provider = FeedProvider::new(&RssFeedOptions { url: file.path().display().to_string(), dithering: None }).unwrap();
+        assert!(provider.next_item(Utc::now()).is_err());
+        assert_eq!(provider.current_item(), None);
+
+        assert_eq!(strip_html("<p>Fish &amp; <b>chips</b></p>\n<p>with  vinegar</p>"), "Fish & chips with vinegar");
+    }
+
+    #[test]
+    fn test_local_images() {
+        let feed = |media: &str| feed_rs::parser::parse(format!(r#"<?xml version="1.0"?>
+            <rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/"><channel><title>Local</title>
+            <item><title>Entry</title>{}</item>
+            </channel></rss>"#, media).as_bytes()).unwrap();
+        let image_url = |media: &str| newest_item(&feed(media)).unwrap().image_url;
+
+        // Local files are skipped in favor of the next image
+        assert_eq!(image_url(r#"<media:thumbnail url="file:///etc/secret.png"/>"#), None);
+        assert_eq!(
+            image_url(r#"<media:content url="file:///etc/secret.png" medium="image"/><media:content url="https://example.com/a.png" medium="image"/>"#),
+            Some("https://example.com/a.png".to_string())
+        );
+        assert_eq!(
+            image_url(r#"<media:thumbnail url="file:///etc/secret.png"/><media:thumbnail url="https://example.com/b.png"/>"#),
+            Some("https://example.com/b.png".to_string())
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits, Rgb, RgbImage};
+use imageproc::drawing::{draw_hollow_rect_mut, draw_line_segment_mut};
+use imageproc::rect::Rect;
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use url::Url;
+
+/// Images larger than this in either dimension aren't decoded
+const MAX_DIMENSION: u32 = 10_000;
+/// Memory the decoder may allocate for one image
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+/// Local photos straight from a camera are often larger than `max_size_mb`, but can't be larger than what can be decoded
+const MAX_FILE_BYTES: u64 = MAX_DECODE_BYTES;
+
+/// How images of the lower panel are loaded
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ImageOptions {
+    /// Downloaded images are kept in this folder, so they aren't downloaded again for every render. No cache by default.
+    pub cache_dir: Option<PathBuf>,
+    /// Cached images are downloaded again after this time.
+    /// If that fails, the cached image is still used. Cached images that weren't downloaded again in this time are deleted.
+    pub cache_hours: u32,
+    /// Timeout of a whole download
+    pub timeout_seconds: u64,
+    /// Larger images aren't downloaded. Local files only have to fit into the decoder.
+    pub max_size_mb: u64,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            cache_dir: None,
+            cache_hours: 24,
+            timeout_seconds: 30,
+            max_size_mb: 10,
+        }
+    }
+}
+
+impl ImageOptions {
+    fn max_bytes(&self) -> u64 {
+        self.max_size_mb * 1024 * 1024
+    }
+
+    fn max_age(&self) -> Duration {
+        Duration::from_secs(u64::from(self.cache_hours) * 3600)
+    }
+}
+
+/// Loads the images of the lower panel. All downloads share one connection pool.
+pub struct ImageLoader {
+    client: Client,
+    options: ImageOptions,
+}
+
+impl ImageLoader {
+    pub fn new(options: &ImageOptions) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(options.timeout_seconds)).build()?;
+        Ok(ImageLoader { client, options: options.clone() })
+    }
+
+    /// Load an image from a http(s) or file URL. Photos are turned upright according to their EXIF orientation
+    /// and animated GIFs show their first frame.
+    pub fn load(&self, url: &str) -> Result<DynamicImage> {
+        let url = Url::parse(url).with_context(|| format!("Invalid image URL {}", url))?;
+
+        match url.scheme() {
+            "file" => {
+                let path = url.to_file_path().map_err(|_| anyhow!("Invalid file URL: {}", url))?;
+                let file = File::open(&path).with_context(|| format!("Couldn't read image {}", path.display()))?;
+                decode_image(&read_limited(file, MAX_FILE_BYTES).with_context(|| format!("Couldn't read image {}", path.display()))?)
+            }
+            "http" | "https" => match &self.options.cache_dir {
+                Some(cache_dir) => self.load_cached(&url, cache_dir),
+                None => decode_image(&self.download(&url)?),
+            },
+            scheme => bail!("Unsupported image URL scheme {}", scheme),
+        }
+    }
+
+    /// Use the cached image while it's fresh. Otherwise download it again, and fall back to the cached image if that fails.
+    fn load_cached(&self, url: &Url, cache_dir: &Path) -> Result<DynamicImage> {
+        let path = cache_path(cache_dir, url);
+        let age = file_age(&path);
+
+        if age.is_some_and(|age| age < self.options.max_age()) {
+            match fs::read(&path).map_err(anyhow::Error::from).and_then(|bytes| decode_image(&bytes)) {
+                Ok(image) => {
+                    debug!(url = %url, "Using cached image");
+                    return Ok(image);
+                }
+                Err(e) => warn!(url = %url, error = %format!("{:#}", e), "Ignoring broken cached image"),
+            }
+        }
+
+        let downloaded = self.download(url).and_then(|bytes| decode_image(&bytes).map(|image| (bytes, image)));
+        match downloaded {
+            Ok((bytes, image)) => {
+                // Only images that could be decoded are cached
+                if let Err(e) = store(cache_dir, &path, &bytes) {
+                    warn!(path = %path.display(), error = %format!("{:#}", e), "Couldn't cache image");
+                }
+                if let Err(e) = prune(cache_dir, &path, self.options.max_age()) {
+                    warn!(path = %cache_dir.display(), error = %format!("{:#}", e), "Couldn't delete old cached images");
+                }
+                Ok(image)
+            }
+            Err(e) if age.is_some() => {
+                warn!(url = %url, error = %format!("{:#}", e), "Couldn't download image. Using expired cached image.");
+                decode_image(&fs::read(&path)?)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Download the image. Responses that aren't images or are larger than `max_size_mb` are rejected.
+    fn download(&self, url: &Url) -> Result<Vec<u8>> {
+        debug!(url = %url, "Downloading image");
+        let response = self.client.get(url.clone()).send()?.error_for_status()?;
+        let max_bytes = self.options.max_bytes();
+
+        // Servers that don't send a type are given the benefit of the doubt. The decoder rejects what isn't an image.
+        if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
+            let content_type = content_type.to_str().unwrap_or_default();
+            if !content_type.trim().to_ascii_lowercase().starts_with("image/") {
+                bail!("{} isn't an image but {}", url, content_type);
+            }
+        }
+        if let Some(length) = response.content_length() {
+            if length > max_bytes {
+                bail!("{} has {} bytes, more than the limit of {} MB", url, length, self.options.max_size_mb);
+            }
+        }
+
+        // The length header might be missing or wrong
+        read_limited(response, max_bytes).with_context(|| format!("Couldn't download {}", url))
+    }
+}
+
+/// The file name is the SHA-256 hash of the URL
+fn cache_path(cache_dir: &Path, url: &Url) -> PathBuf {
+    cache_dir.join(format!("{:x}", Sha256::digest(url.as_str().as_bytes())))
+}
+
+fn file_age(path: &Path) -> Option<Duration> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok().and_then(|modified| modified.elapsed().ok())
+}
+
+/// Write the file atomically, so that a crash never leaves half an image in the cache
+fn store(cache_dir: &Path, path: &Path, bytes: &[u8]) -> Result<()> {
+    fs::create_dir_all(cache_dir)?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, bytes)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Delete the cached images except `keep` that are older than `max_age`, so that the cache doesn't grow forever.
+/// Only files named like cached images are touched, in case the folder is shared.
+fn prune(cache_dir: &Path, keep: &Path, max_age: Duration) -> Result<()> {
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let hash = name.strip_suffix(".tmp").unwrap_or(name);
+        let is_cached_image = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+
+        if is_cached_image && path != keep && path.is_file() && file_age(&path).is_some_and(|age| age > max_age) {
+            debug!(path = %path.display(), "Deleting old cached image");
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read at most `max_bytes`. Longer input is an error instead of being cut off.
+pub(crate) fn read_limited(reader: impl Read, max_bytes: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(max_bytes + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_bytes {
+        bail!("Larger than the limit of {} bytes", max_bytes);
+    }
+    Ok(bytes)
+}
+
+fn decode_image(bytes: &[u8]) -> Result<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    match reader.format() {
+        Some(ImageFormat::Gif) => {
+            // Frames of animations can be smaller than the image, so the first frame is composed like a player would
+            let mut decoder = GifDecoder::new(Cursor::new(bytes))?;
+            decoder.set_limits(limits)?;
+            let frame = decoder.into_frames().next().ok_or_else(|| anyhow!("GIF without frames"))??;
+            Ok(DynamicImage::ImageRgba8(frame.into_buffer()))
+        }
+        Some(_) => {
+            reader.limits(limits);
+            let mut decoder = reader.into_decoder()?;
+            let orientation = decoder.orientation()?;
+            let mut image = DynamicImage::from_decoder(decoder)?;
+            image.apply_orientation(orientation);
+            Ok(image)
+        }
+        None => bail!("Unknown image format"),
+    }
+}
+
+/// Shown instead of an image that couldn't be loaded: a crossed out box
+pub fn placeholder(width: u32, height: u32) -> DynamicImage {
+    let black = Rgb([0, 0, 0]);
+    let mut image = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    if width < 2 || height < 2 {
+        return DynamicImage::ImageRgb8(image);
+    }
+
+    draw_hollow_rect_mut(&mut image, Rect::at(0, 0).of_size(width, height), black);
+    let (right, bottom) = ((width - 1) as f32, (height - 1) as f32);
+    draw_line_segment_mut(&mut image, (0.0, 0.0), (right, bottom), black);
+    draw_line_segment_mut(&mut image, (0.0, bottom), (right, 0.0), black);
+    DynamicImage::ImageRgb8(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use image::codecs::gif::GifEncoder;
+    use image::{Frame, GenericImageView, Rgba, RgbaImage};
+    use tiny_http::{Header, Response, Server};
+    use super::*;
+
+    fn fixture_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    /// Animation with a red and a blue frame
+    fn animated_gif() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for color in [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])] {
+                encoder.encode_frame(Frame::new(RgbaImage::from_pixel(4, 4, color))).unwrap();
+            }
+        }
+        bytes
+    }
+
+    /// Serve test images and count the requests
+    fn serve() -> (String, Arc<AtomicUsize>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            let png = fs::read(fixture_path("article.png")).unwrap();
+            let content_type = |value: &str| Header::from_bytes("Content-Type", value).unwrap();
+            for request in server.incoming_requests() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let response = match request.url() {
+                    "/image.png" => Response::from_data(png.clone()).with_header(content_type("image/png")),
+                    "/untyped.png" => Response::from_data(png.clone()),
+                    "/animated.gif" => Response::from_data(animated_gif()).with_header(content_type("image/gif")),
+                    "/page.html" => Response::from_data(b"<html></html>".to_vec()).with_header(content_type("text/html")),
+                    "/huge.png" => Response::from_data(vec![0; 2 * 1024 * 1024]).with_header(content_type("image/png")),
+                    "/broken.png" => Response::from_data(b"not a png".to_vec()).with_header(content_type("image/png")),
+                    _ => Response::from_data(Vec::new()).with_status_code(404),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_download() {
+        let (url, _) = serve();
+        let loader = ImageLoader::new(&ImageOptions { max_size_mb: 1, ..ImageOptions::default() }).unwrap();
+        let load = |path: &str| loader.load(&format!("{}{}", url, path));
+
+        let fixture = image::open(fixture_path("article.png")).unwrap();
+        assert_eq!(load("/image.png").unwrap().dimensions(), fixture.dimensions());
+        assert_eq!(load("/untyped.png").unwrap().dimensions(), fixture.dimensions());
+
+        let gif = load("/animated.gif").unwrap();
+        assert_eq!(gif.dimensions(), (4, 4));
+        assert_eq!(gif.to_rgba8().get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+
+        for path in ["/page.html", "/huge.png", "/broken.png", "/missing.png"] {
+            assert!(load(path).is_err(), "{}", path);
+        }
+        assert!(loader.load("ftp://example.com/image.png").is_err());
+    }
+
+    #[test]
+    fn test_cache() {
+        let (url, requests) = serve();
+        let dir = tempfile::tempdir().unwrap();
+        let options = ImageOptions { cache_dir: Some(dir.path().join("images")), ..ImageOptions::default() };
+        let loader = ImageLoader::new(&options).unwrap();
+
+        let image_url = format!("{}/image.png", url);
+        let first = loader.load(&image_url).unwrap();
+        let cached = loader.load(&image_url).unwrap();
+        assert_eq!(first, cached);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        let cache_file = cache_path(&dir.path().join("images"), &Url::parse(&image_url).unwrap());
+        assert!(cache_file.is_file());
+
+        // Broken images aren't cached
+        assert!(loader.load(&format!("{}/broken.png", url)).is_err());
+        assert_eq!(fs::read_dir(dir.path().join("images")).unwrap().count(), 1);
+
+        // An expired image is downloaded again, and still used when that fails
+        let expired = ImageLoader::new(&ImageOptions { cache_hours: 0, ..options.clone() }).unwrap();
+        expired.load(&image_url).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        let missing_url = format!("{}/missing.png", url);
+        let missing_file = cache_path(&dir.path().join("images"), &Url::parse(&missing_url).unwrap());
+        fs::copy(&cache_file, &missing_file).unwrap();
+        assert_eq!(expired.load(&missing_url).unwrap(), first);
+
+        // Storing an image deletes the other expired images, but not the files of others
+        let other_file = dir.path().join("images/notes.txt");
+        fs::write(&other_file, "mine").unwrap();
+        thread::sleep(Duration::from_millis(10));
+        expired.load(&image_url).unwrap();
+        assert!(cache_file.is_file());
+        assert!(!missing_file.exists());
+        assert!(other_file.is_file());
+    }
+
+    #[test]
+    fn test_file() {
+        let loader = ImageLoader::new(&ImageOptions::default()).unwrap();
+        let url = Url::from_file_path(fixture_path("article.png")).unwrap();
+        assert!(loader.load(url.as_str()).is_ok());
+        // The download limit doesn't apply to local photos
+        let small_downloads = ImageLoader::new(&ImageOptions { max_size_mb: 0, ..ImageOptions::default() }).unwrap();
+        assert!(small_downloads.load(url.as_str()).is_ok());
+        assert!(read_limited(fs::File::open(fixture_path("article.png")).unwrap(), 100).is_err());
+
+        let missing = Url::from_file_path(fixture_path("missing.png")).unwrap();
+        assert!(loader.load(missing.as_str()).is_err());
+
+        let image = placeholder(30, 20).to_rgb8();
+        assert_eq!(image.dimensions(), (30, 20));
+        assert_eq!(image.get_pixel(0, 10), &Rgb([0, 0, 0]));
+        assert_eq!(image.get_pixel(15, 1), &Rgb([255, 255, 255]));
+    }
+}
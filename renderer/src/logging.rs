@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line with the fields of the event and its spans
+    Json,
+}
+
+/// Install the global logger.
+/// Logs go to stderr, so that the output of commands like `fetch --json` can still be piped.
+pub fn init(level: &str, format: LogFormat) -> Result<()> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter(level)?)
+        .with_writer(std::io::stderr);
+
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+    }
+    Ok(())
+}
+
+/// Parse a level like `debug` or directives per crate like `info,knowyourmeme=debug`
+fn filter(level: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(level).with_context(|| format!("Invalid log level \"{}\"", level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        assert!(filter("debug").is_ok());
+        assert!(filter("info,knowyourmeme=debug").is_ok());
+        assert!(filter("knowyourmeme=loud").is_err());
+    }
+}
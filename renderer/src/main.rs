@@ -1,122 +1,485 @@
-use std::thread;
-use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, Timelike, Utc};
-use reqwest::Url;
-use reqwest::blocking::Client;
-use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike};
+use anyhow::{bail, Context, Result};
+use chrono_tz::Tz;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, info_span, warn};
 use brightsky::{self, CurrentWeather, HourlyWeather};
 
+mod cli;
+use cli::{Cli, Command};
+mod logging;
 mod config;
-use config::CONFIG;
+use config::Config;
+mod clock;
+use clock::{Clock, FixedClock, SystemClock};
 mod weather;
 use weather::WeatherData;
 mod text;
 mod dithering;
 mod drawing;
 mod rendering;
+mod content;
+use content::{ContentItem, ContentProvider};
+mod feed_provider;
+mod images;
+use images::ImageLoader;
+mod photo_provider;
+mod meme_history;
+mod meme_provider;
+mod quote_provider;
+mod metrics;
+use metrics::{Metrics, SOURCE_CONTENT, SOURCE_CURRENT_WEATHER, SOURCE_WEATHER_FORECAST};
+mod server;
+use server::{HealthCheck, ImageStore, RenderedImage};
+mod signals;
+use signals::{Event, SignalEvents};
+mod webdav;
+use webdav::WebDavClient;
+
+/// Address of the HTTP server for `serve` if the config has none
+const DEFAULT_SERVER_ADDRESS: &str = "0.0.0.0:8080";
 
 pub struct DisplayData<'a> {
+    /// Point in time the image is rendered for
+    date_time: DateTime<Tz>,
     weather: WeatherData<'a>,
-    kym_article: Option<knowyourmeme::Article>
+    /// Item of the lower panel
+    content: Option<ContentItem>,
 }
 
-fn main() -> Result<()> {
-    let date_time: DateTime<FixedOffset> = Local::now().into();
+/// Everything an image is rendered from.
+/// Written by `fetch --json` and read by `preview --fixture`.
+#[derive(Debug, Serialize, Deserialize)]
+struct FetchedData {
+    date_time: DateTime<FixedOffset>,
+    current_weather: CurrentWeather,
+    weather_forecast: Vec<HourlyWeather>,
+    #[serde(default, alias = "kym_article", deserialize_with = "deserialize_content")]
+    content: Option<ContentItem>,
+}
 
-    println!("Getting weather data...");
-    let mut current_weather = brightsky::get_current_weather(&CONFIG.location, &CONFIG.timezone)?;
-    let mut weather_forecast = brightsky::get_weather_forecast(&date_time, 5, &CONFIG.location, &CONFIG.timezone);
+/// Fixtures written before the content providers have a Know Your Meme article as `kym_article`
+fn deserialize_content<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<ContentItem>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Content {
+        Item(ContentItem),
+        Article(knowyourmeme::Article),
+    }
 
-    let mut last_date_time = date_time;
-    let mut last_current_weather = current_weather.clone();
-    let mut last_weather_forecast = weather_forecast.clone();
+    Ok(Option::<Content>::deserialize(deserializer)?.map(|content| match content {
+        Content::Item(item) => item,
+        Content::Article(article) => meme_provider::article_item(&article),
+    }))
+}
 
-    let mut last_kym_article = None;
+/// Where rendered images go
+#[derive(Default)]
+struct Outputs {
+    /// Upload to the WebDAV server of the config if it has one
+    upload: bool,
+    /// Serve on this address
+    serve: Option<String>,
+    /// Save as PNG to this path
+    save_to: Option<PathBuf>,
+}
 
-    loop {
-        println!("Getting Know Your Meme article...");
-        let mut kym_article = knowyourmeme::get_newest_meme_article().ok();
-
-        // Use last article if current article is invalid
-        // or save current article for later usage
-        if kym_article.is_none() {
-            kym_article = last_kym_article.clone();
-            println!("Couldn't find a new Know Your Meme article. Using last article.");
-        } else {
-            last_kym_article = kym_article.clone();
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    logging::init(&cli.log_level, cli.log_format)?;
+    let config = Config::load(cli.config.as_deref())?;
+
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => {
+            let outputs = Outputs {
+                upload: true,
+                serve: config.server_address.clone(),
+                // Save image as PNG in debug mode
+                save_to: cfg!(debug_assertions).then(|| PathBuf::from("output.png")),
+            };
+            run(config, cli.config.as_deref(), &SystemClock, &outputs, false)
+        }
+        Command::Render { once, out } => {
+            let outputs = Outputs { save_to: Some(out), ..Default::default() };
+            run(config, cli.config.as_deref(), &SystemClock, &outputs, once)
+        }
+        Command::Serve { address } => {
+            let address = address.or(config.server_address.clone()).unwrap_or(DEFAULT_SERVER_ADDRESS.to_string());
+            let outputs = Outputs { serve: Some(address), ..Default::default() };
+            run(config, cli.config.as_deref(), &SystemClock, &outputs, false)
+        }
+        Command::Upload => {
+            if config.webdav_url.is_none() {
+                bail!("Can't upload because webdav_url isn't configured");
+            }
+            let outputs = Outputs { upload: true, ..Default::default() };
+            run(config, cli.config.as_deref(), &SystemClock, &outputs, true)
         }
+        Command::Fetch { json, at } => fetch_command(&config, at.as_deref(), json),
+        Command::ValidateConfig => {
+            // The config was already validated when it was loaded
+            println!("Config is valid");
+            Ok(())
+        }
+        Command::Preview { at, fixture, out } => preview(&config, at.as_deref(), fixture.as_deref(), &out),
+    }
+}
 
-        // Render image
-        render(&current_weather, &weather_forecast, kym_article)?;
+/// Render a new image at every refresh time of the schedule or only once.
+///
+/// SIGTERM and SIGINT stop the loop after the current render and upload. SIGHUP and SIGUSR1 reload
+/// the config from `config_path` and render a new image right away.
+fn run(mut config: Config, config_path: Option<&str>, clock: &dyn Clock, outputs: &Outputs, once: bool) -> Result<()> {
+    let image_store = ImageStore::default();
+    let metrics = Metrics::new();
+    let health_check = Arc::new(RwLock::new(HealthCheck::from_config(&config)));
+    if let Some(address) = &outputs.serve {
+        server::spawn(address, config.image_name.clone(), image_store.clone(), metrics.clone(), health_check.clone())?;
+    }
+
+    // Only the service handles signals. A single render can be stopped right away.
+    let events = if once { SignalEvents::channel().1 } else { SignalEvents::listen()? };
 
-        wait_until_next_hour()?;
-        
-        println!();
+    let mut last_date_time: Option<DateTime<FixedOffset>> = None;
+    let mut last_current_weather: Option<CurrentWeather> = None;
+    let mut last_weather_forecast: Vec<HourlyWeather> = Vec::new();
+    let mut content_provider = content::provider(&config)?;
+    let mut image_loader = ImageLoader::new(&config.images)?;
 
-        // Get new data
-        let date_time: DateTime<FixedOffset> = chrono::Local::now().into();
+    let mut cycle: u64 = 0;
+    loop {
+        cycle += 1;
+        let date_time: DateTime<FixedOffset> = clock.now_in(&config.timezone).fixed_offset();
+
+        let span = info_span!("render_cycle", cycle, %date_time);
+        let entered = span.enter();
 
-        println!("Getting weather data...");
-        current_weather = match brightsky::get_current_weather(&CONFIG.location, &CONFIG.timezone) {
+        info!("Getting weather data");
+        let current_weather = match metrics.observe_fetch(SOURCE_CURRENT_WEATHER, || brightsky::get_current_weather(&config.location, &config.timezone)) {
             Ok(w) => w,
-            Err(_) => last_current_weather.clone(),
+            Err(e) => match last_current_weather.take() {
+                Some(last) => {
+                    warn!(error = %format!("{:#}", e), "Couldn't get current weather. Using last current weather.");
+                    last
+                }
+                // Without any weather there's nothing to render
+                None => return Err(e.context("Couldn't get current weather")),
+            },
         };
 
-        weather_forecast = brightsky::get_weather_forecast(&date_time, 5, &CONFIG.location, &CONFIG.timezone);
+        let mut weather_forecast = fetch_weather_forecast(&config, &date_time, &metrics);
         // Get last entry if current is invalid
-        if weather_forecast.len() < 4 {
-            println!("Error: Weather forecast has less than 4 entries. Using last weather forecast data.");
-            
-            if date_time.day() != last_date_time.day() {
+        if let (true, Some(last_date_time)) = (weather_forecast.len() < 4, last_date_time) {
+            warn!(entries = weather_forecast.len(), "Weather forecast has less than 4 entries. Using last weather forecast data.");
+
+            if date_time.day() != last_date_time.day() && !last_weather_forecast.is_empty() {
                 last_weather_forecast.remove(0); // Remove last day so that new day is the first
             }
 
             weather_forecast = last_weather_forecast.clone();
         }
 
+        let content = select_content(content_provider.as_mut(), &metrics, clock);
+
+        // Render image
+        render(&config, clock, &image_loader, &image_store, &metrics, outputs, once, &current_weather, &weather_forecast, content.clone())?;
+
+        if let Some(item) = &content {
+            if let Err(e) = content_provider.shown(item, clock.now()) {
+                warn!(error = %format!("{:#}", e), "Couldn't remember the shown content");
+            }
+        }
+
         // Update last data
-        last_date_time = date_time;
-        last_current_weather = current_weather.clone();
-        last_weather_forecast = weather_forecast.clone();
+        last_date_time = Some(date_time);
+        last_current_weather = Some(current_weather);
+        last_weather_forecast = weather_forecast;
+
+        if once {
+            return Ok(());
+        }
+
+        drop(entered);
+        match wait_until_next_refresh(&config, clock, &events)? {
+            None => {}
+            Some(Event::Shutdown) => {
+                info!("Shutting down");
+                return Ok(());
+            }
+            Some(Event::Reload) => match Config::load(config_path) {
+                Ok(new_config) => {
+                    if new_config.server_address != config.server_address {
+                        warn!("The new server_address is used after a restart");
+                    }
+                    *health_check.write().unwrap() = HealthCheck::from_config(&new_config);
+                    match content::provider(&new_config) {
+                        Ok(provider) => content_provider = provider,
+                        Err(e) => error!(error = %format!("{:#}", e), "Couldn't create content provider. Keeping the current provider."),
+                    }
+                    match ImageLoader::new(&new_config.images) {
+                        Ok(loader) => image_loader = loader,
+                        Err(e) => error!(error = %format!("{:#}", e), "Couldn't create image loader. Keeping the current loader."),
+                    }
+                    config = new_config;
+                    info!("Reloaded config");
+                }
+                Err(e) => error!(error = %format!("{:#}", e), "Couldn't reload config. Keeping the current config."),
+            },
+        }
+    }
+}
+
+/// Get the item of the lower panel from the provider.
+/// If that fails, the item that is on the display stays.
+fn select_content(provider: &mut dyn ContentProvider, metrics: &Metrics, clock: &dyn Clock) -> Option<ContentItem> {
+    info!(provider = provider.name(), "Getting content");
+    match metrics.observe_fetch(SOURCE_CONTENT, || provider.next_item(clock.now())) {
+        Ok(item) => item,
+        Err(e) => {
+            warn!(provider = provider.name(), error = %format!("{:#}", e), "Couldn't get content. Using the current item.");
+            provider.current_item()
+        }
     }
 }
 
-fn render(current_weather: &CurrentWeather, weather_forecast: &Vec<HourlyWeather>, kym_article: Option<knowyourmeme::Article>) -> Result<()> {
+/// Get the weather forecast and count forecasts with less than 4 entries as failures
+fn fetch_weather_forecast(config: &Config, date_time: &DateTime<FixedOffset>, metrics: &Metrics) -> Vec<HourlyWeather> {
+    let weather_forecast = metrics.observe_fetch(SOURCE_WEATHER_FORECAST, || {
+        Ok(brightsky::get_weather_forecast(date_time, 5, &config.location, &config.timezone))
+    }).unwrap_or_default();
+
+    if weather_forecast.len() < 4 {
+        metrics.fetch_failed(SOURCE_WEATHER_FORECAST);
+    }
+    weather_forecast
+}
+
+/// Get the weather and the content for the given time.
+/// For other times than now, the current weather is taken from the forecast because the current weather endpoint only knows the present.
+fn fetch(config: &Config, date_time: DateTime<Tz>, is_now: bool) -> Result<FetchedData> {
+    let weather_forecast = brightsky::get_weather_forecast(&date_time.fixed_offset(), 5, &config.location, &config.timezone);
+
+    let current_weather = if is_now {
+        brightsky::get_current_weather(&config.location, &config.timezone)?
+    } else {
+        weather_forecast.iter()
+            .find(|w| w.timestamp.date_naive() == date_time.date_naive() && w.timestamp.hour() == date_time.hour())
+            .context("No weather data found for this time")?
+            .clone()
+            .into()
+    };
+
+    // Only chosen, not recorded as shown
+    let content = content::provider(config)?.next_item(date_time.to_utc())
+        .unwrap_or_else(|e| {
+            warn!(error = %format!("{:#}", e), "Couldn't get content");
+            None
+        });
+
+    Ok(FetchedData {
+        date_time: date_time.fixed_offset(),
+        current_weather,
+        weather_forecast,
+        content,
+    })
+}
+
+/// Print the data an image would be rendered from
+fn fetch_command(config: &Config, at: Option<&str>, json: bool) -> Result<()> {
+    let date_time = match at {
+        Some(at) => FixedClock::parse(at, &config.timezone)?.now_in(&config.timezone),
+        None => SystemClock.now_in(&config.timezone),
+    };
+    let data = fetch(config, date_time, at.is_none())?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&data)?);
+        return Ok(());
+    }
+
+    let weather = &data.current_weather;
+    println!("Time: {}", data.date_time);
+    println!(
+        "Current weather: {}°C, {}% humidity, {} km/h wind, {:?}",
+        weather.temperature.unwrap_or(f32::NAN),
+        weather.relative_humidity.unwrap_or(f32::NAN),
+        weather.wind_speed_10.unwrap_or(f32::NAN),
+        weather.condition,
+    );
+    match (data.weather_forecast.first(), data.weather_forecast.last()) {
+        (Some(first), Some(last)) => println!(
+            "Forecast: {} hours from {} to {}",
+            data.weather_forecast.len(), first.timestamp, last.timestamp
+        ),
+        _ => println!("Forecast: No data"),
+    }
+    match &data.content {
+        Some(item) => {
+            println!("Content: {} ({})", item.title, item.id);
+            if let Some(image_url) = &item.image_url {
+                println!("  Image: {}", image_url);
+            }
+        }
+        None => println!("Content: Nothing to show"),
+    }
+
+    Ok(())
+}
+
+/// Render a single image for any time and save it without uploading it.
+/// With a fixture, the network isn't used at all.
+fn preview(config: &Config, at: Option<&str>, fixture: Option<&Path>, out: &Path) -> Result<()> {
+    let clock: Option<FixedClock> = at.map(|at| FixedClock::parse(at, &config.timezone)).transpose()?;
+
+    let data = match fixture {
+        Some(path) => {
+            let json = fs::read_to_string(path).with_context(|| format!("Couldn't read fixture {}", path.display()))?;
+            serde_json::from_str(&json).with_context(|| format!("Invalid fixture {}", path.display()))?
+        }
+        None => {
+            let date_time = match &clock {
+                Some(clock) => clock.now_in(&config.timezone),
+                None => SystemClock.now_in(&config.timezone),
+            };
+            info!(%date_time, "Getting data");
+            fetch(config, date_time, clock.is_none())?
+        }
+    };
+
+    let date_time = match &clock {
+        Some(clock) => clock.now_in(&config.timezone),
+        None => data.date_time.with_timezone(&config.timezone),
+    };
+
     let display_data = DisplayData {
+        date_time,
+        weather: WeatherData {
+            current_weather: &data.current_weather,
+            weather_forecast: &data.weather_forecast,
+        },
+        content: data.content,
+    };
+
+    info!("Rendering image");
+    let image = rendering::render_image(display_data, config, &ImageLoader::new(&config.images)?)?;
+    image.save(out).context("Couldn't save image")?;
+    info!(path = %out.display(), "Saved preview");
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render(config: &Config, clock: &dyn Clock, images: &ImageLoader, image_store: &ImageStore, metrics: &Metrics, outputs: &Outputs, once: bool, current_weather: &CurrentWeather, weather_forecast: &Vec<HourlyWeather>, content: Option<ContentItem>) -> Result<()> {
+    let rendered_at = clock.now();
+    let display_data = DisplayData {
+        date_time: rendered_at.with_timezone(&config.timezone),
         weather: WeatherData {
             current_weather,
             weather_forecast,
         },
-        kym_article
+        content,
     };
 
-    println!("Rendering image...");
-    let image_buffer = rendering::render_image(display_data)?;
+    info!("Rendering image");
+    let image = metrics.observe_render(|| rendering::render_image(display_data, config, images))?;
 
-    println!("Uploading image...");
-    let base_url = Url::parse(CONFIG.webdav_url)?;
-    let image_url = base_url.join(CONFIG.image_name)?;
-    let image_url_str = image_url.to_string();
+    if let Some(path) = &outputs.save_to {
+        image.save(path).context("Couldn't save image")?;
+        info!(path = %path.display(), "Saved image");
+    }
 
-    let response = Client::new().put(image_url).body(image_buffer).send()?;
+    let rendered_image = RenderedImage::new(&image, rendered_at, current_weather.timestamp.to_utc())?;
 
-    if response.status().is_success() {
-        println!("Image upload successful: {}", image_url_str);
-    } else {
-        println!("Couldn't upload picture. Status code: {}", response.status());
+    if let (true, Some(webdav_url)) = (outputs.upload, &config.webdav_url) {
+        let result = upload(config, webdav_url, &rendered_image.epd_buffer);
+        metrics.uploaded(result.is_ok(), clock.now());
+        if let Err(e) = result {
+            // A failed upload shouldn't stop the service. The next render tries again.
+            if once {
+                return Err(e.context("Couldn't upload image"));
+            }
+            error!(error = %format!("{:#}", e), "Couldn't upload image");
+        }
     }
+
+    // Serve the new image
+    metrics.rendered(rendered_at, current_weather.timestamp);
+    image_store.set(rendered_image);
+
     Ok(())
 }
 
-fn wait_until_next_hour() -> Result<()> {
-    let now = Utc::now().with_timezone(&CONFIG.timezone);
-    
-    let next_hour = now.checked_add_signed(Duration::hours(1)).unwrap();
-    let next_hour = next_hour.with_minute(0).unwrap().with_second(0).unwrap().with_nanosecond(0).unwrap();
-    let next_hour_seconds = next_hour.signed_duration_since(now).to_std()?;
-
-    // Wait until next hour starts
-    println!("Waiting {}s ({:?})", next_hour_seconds.as_secs(), next_hour);
-    thread::sleep(next_hour_seconds);
+fn upload(config: &Config, webdav_url: &str, image_buffer: &rendering::EpdBuffer) -> Result<()> {
+    info!("Uploading image");
+    let client = WebDavClient::new(webdav_url, config.webdav_auth.clone())?;
+    let image_url = client.upload(&config.image_name, image_buffer)?;
 
+    info!(url = %image_url, "Uploaded image");
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Wait until the next refresh time of the schedule or until a signal is received.
+/// Signals that arrived during the last render are handled right away.
+fn wait_until_next_refresh(config: &Config, clock: &dyn Clock, events: &SignalEvents) -> Result<Option<Event>> {
+    if let Some(event) = events.pending() {
+        return Ok(Some(event));
+    }
+
+    let now = clock.now_in(&config.timezone);
+
+    let next_refresh = config.schedule.next_after(&now);
+    let wait_time = next_refresh.signed_duration_since(now).to_std()?;
+
+    info!(seconds = wait_time.as_secs(), %next_refresh, "Waiting for next refresh");
+    Ok(events.wait(wait_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_round_trip() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fetched_data.json");
+        let data: FetchedData = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(data.weather_forecast.len(), 96);
+        assert_eq!(data.current_weather.timestamp, data.date_time);
+
+        // Output of `fetch --json` can be read by `preview --fixture`
+        let json = serde_json::to_string(&data).unwrap();
+        let parsed: FetchedData = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.current_weather, data.current_weather);
+        assert_eq!(parsed.weather_forecast, data.weather_forecast);
+    }
+
+    #[test]
+    fn test_old_fixture() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fetched_data.json");
+        let mut json: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("content");
+        fields.insert("kym_article".to_string(), serde_json::json!({
+            "url": "/memes/dancing-cat",
+            "title": "Dancing Cat",
+            "summary": "A cat that dances.",
+            "image_url": "https://example.com/cat.jpg",
+            "meme_name": "Dancing Cat"
+        }));
+
+        // The article is shown like the meme provider shows it
+        let data: FetchedData = serde_json::from_value(json.clone()).unwrap();
+        let item = data.content.unwrap();
+        assert_eq!(item.id, "/memes/dancing-cat");
+        assert_eq!(item.subtitle.as_deref(), Some("Dancing Cat"));
+        assert_eq!(item.image_url.as_deref(), Some("https://example.com/cat.jpg"));
+
+        json["kym_article"] = serde_json::Value::Null;
+        assert!(serde_json::from_value::<FetchedData>(json.clone()).unwrap().content.is_none());
+        json.as_object_mut().unwrap().remove("kym_article");
+        assert!(serde_json::from_value::<FetchedData>(json).unwrap().content.is_none());
+    }
+}
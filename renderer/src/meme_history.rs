@@ -0,0 +1,275 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use knowyourmeme::{Article, FeedOptions};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// How the meme on the display is chosen from the front page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionPolicy {
+    /// Always the newest article
+    #[default]
+    Newest,
+    /// The newest article that wasn't shown recently. The current article stays until a new one appears.
+    NewestUnseen,
+    /// Show every article for `rotate_hours`, then move on to the next one that wasn't shown recently
+    Rotate,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MemeSelection {
+    pub policy: SelectionPolicy,
+    pub rotate_hours: u32,
+    /// Articles aren't shown again within this many days unless there's nothing else
+    pub repeat_after_days: u32,
+    /// Where shown articles are remembered across restarts. Without a path, they're only kept in memory.
+    pub history_path: Option<PathBuf>,
+    /// Which articles of the front page can be chosen
+    pub feed: FeedOptions,
+}
+
+impl Default for MemeSelection {
+    fn default() -> Self {
+        MemeSelection {
+            policy: SelectionPolicy::Newest,
+            rotate_hours: 6,
+            repeat_after_days: 7,
+            history_path: None,
+            feed: FeedOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HistoryEntry {
+    article: Article,
+    /// When the article was put on the display the last time
+    shown_since: DateTime<Utc>,
+    /// Last render with the article
+    last_shown: DateTime<Utc>,
+}
+
+/// Articles that were shown on the display, the current one last
+#[derive(Debug, Default)]
+pub struct MemeHistory {
+    path: Option<PathBuf>,
+    entries: Vec<HistoryEntry>,
+}
+
+impl MemeHistory {
+    /// Load the history from the file. A missing or broken file starts a new history.
+    pub fn load(path: Option<&Path>) -> Self {
+        let entries = match path.map(|path| (path, fs::read_to_string(path))) {
+            Some((_, Ok(json))) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!(error = %e, "Couldn't parse meme history. Starting a new history.");
+                Vec::new()
+            }),
+            Some((_, Err(e))) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Some((path, Err(e))) => {
+                warn!(error = %e, path = %path.display(), "Couldn't read meme history. Starting a new history.");
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        debug!(entries = entries.len(), "Loaded meme history");
+
+        MemeHistory { path: path.map(Path::to_path_buf), entries }
+    }
+
+    /// Article that is on the display, e.g. to show it again if the front page can't be fetched
+    pub fn current(&self) -> Option<&Article> {
+        self.entries.last().map(|entry| &entry.article)
+    }
+
+    /// Choose the article to show from the front page, newest article first.
+    /// Articles in `skip` are never chosen, not even the current one, e.g. because they turned out to be unsuitable.
+    pub fn select(&self, feed: &[Article], skip: &[String], options: &MemeSelection, now: DateTime<Utc>) -> Option<Article> {
+        let feed: Vec<&Article> = feed.iter().filter(|article| !skip.contains(&article.url)).collect();
+        let current = self.entries.last().filter(|entry| !skip.contains(&entry.article.url));
+        let is_current = |article: &Article| current.is_some_and(|entry| entry.article.url == article.url);
+        let seen_recently = |article: &Article| self.entries.iter()
+            .any(|entry| entry.article.url == article.url && now - entry.last_shown < Duration::days(options.repeat_after_days.into()));
+
+        let selected = match options.policy {
+            SelectionPolicy::Newest => feed.first().copied(),
+            SelectionPolicy::NewestUnseen => feed.iter()
+                .find(|article| is_current(article) || !seen_recently(article))
+                .copied()
+                .or_else(|| self.least_recently_shown(feed.iter().copied())),
+            SelectionPolicy::Rotate => {
+                if let Some(current) = current.filter(|entry| now - entry.shown_since < Duration::hours(options.rotate_hours.into())) {
+                    return Some(current.article.clone());
+                }
+                feed.iter()
+                    .find(|article| !is_current(article) && !seen_recently(article))
+                    .copied()
+                    .or_else(|| self.least_recently_shown(feed.iter().copied().filter(|article| !is_current(article))))
+                    .or_else(|| feed.first().copied())
+            }
+        };
+
+        selected.cloned()
+    }
+
+    /// Remember that the article is on the display and save the history.
+    /// Entries that don't matter for the selection anymore are removed.
+    pub fn record(&mut self, article: &Article, options: &MemeSelection, now: DateTime<Utc>) -> Result<()> {
+        let shown_since = match self.entries.last() {
+            Some(current) if current.article.url == article.url => current.shown_since,
+            _ => now,
+        };
+        self.entries.retain(|entry| entry.article.url != article.url);
+
+        let keep = Duration::days(options.repeat_after_days.into());
+        self.entries.retain(|entry| now - entry.last_shown < keep);
+
+        self.entries.push(HistoryEntry { article: article.clone(), shown_since, last_shown: now });
+        self.save()
+    }
+
+    fn least_recently_shown<'a>(&self, articles: impl IntoIterator<Item = &'a Article>) -> Option<&'a Article> {
+        let last_shown = |article: &Article| self.entries.iter()
+            .find(|entry| entry.article.url == article.url)
+            .map(|entry| entry.last_shown);
+        // Unseen articles first, then the one that was shown the longest time ago
+        articles.into_iter().min_by_key(|article| last_shown(article))
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        // Write to a temporary file first, so that a crash doesn't leave a broken history
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(&self.entries)?)
+            .with_context(|| format!("Couldn't write meme history to {}", temp_path.display()))?;
+        fs::rename(&temp_path, path).with_context(|| format!("Couldn't write meme history to {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use knowyourmeme::EntryType;
+    use super::*;
+
+    fn article(name: &str) -> Article {
+        Article {
+            url: format!("/memes/{}", name),
+            title: name.to_string(),
+            summary: String::new(),
+            image_url: format!("https://i.kym-cdn.com/{}.jpg", name),
+            meme_name: None,
+            entry_type: EntryType::Meme,
+            tags: Vec::new(),
+            meme_entry: None,
+            nsfw: false,
+        }
+    }
+
+    fn time(hours: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-12-24T00:00:00Z").unwrap().to_utc() + Duration::hours(hours)
+    }
+
+    fn options(policy: SelectionPolicy) -> MemeSelection {
+        MemeSelection { policy, history_path: None, ..Default::default() }
+    }
+
+    /// Select and record an article like the render loop does
+    fn show(history: &mut MemeHistory, feed: &[Article], options: &MemeSelection, now: DateTime<Utc>) -> String {
+        let article = history.select(feed, &[], options, now).unwrap();
+        history.record(&article, options, now).unwrap();
+        article.title
+    }
+
+    #[test]
+    fn test_newest_unseen() {
+        let options = options(SelectionPolicy::NewestUnseen);
+        let mut history = MemeHistory::default();
+
+        let feed = [article("a"), article("b")];
+        assert_eq!(show(&mut history, &feed, &options, time(0)), "a");
+        // The current article stays
+        assert_eq!(show(&mut history, &feed, &options, time(1)), "a");
+
+        let feed = [article("c"), article("a"), article("b")];
+        assert_eq!(show(&mut history, &feed, &options, time(2)), "c");
+
+        // "c" was removed from the front page and "a" was already shown
+        let feed = [article("a"), article("b")];
+        assert_eq!(show(&mut history, &feed, &options, time(3)), "b");
+    }
+
+    #[test]
+    fn test_rotate() {
+        let options = MemeSelection { rotate_hours: 2, repeat_after_days: 1, ..options(SelectionPolicy::Rotate) };
+        let mut history = MemeHistory::default();
+        let feed = [article("a"), article("b"), article("c")];
+
+        let shown: Vec<String> = (0..8).map(|hour| show(&mut history, &feed, &options, time(hour))).collect();
+        // After all articles were shown, the one that was shown the longest time ago comes again
+        assert_eq!(shown, ["a", "a", "b", "b", "c", "c", "a", "a"]);
+
+        // After a day, entries are forgotten
+        assert_eq!(history.entries.len(), 3);
+        history.record(&article("d"), &options, time(40)).unwrap();
+        assert_eq!(history.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_skip() {
+        let feed = [article("a"), article("b"), article("c")];
+        let skip = [article("a").url];
+        let select = |history: &MemeHistory, options: &MemeSelection, skip: &[String]| {
+            history.select(&feed, skip, options, time(1)).map(|article| article.title)
+        };
+
+        // The current article is skipped even while it should stay
+        for policy in [SelectionPolicy::Newest, SelectionPolicy::NewestUnseen, SelectionPolicy::Rotate] {
+            let options = options(policy);
+            let mut history = MemeHistory::default();
+            history.record(&article("a"), &options, time(0)).unwrap();
+            assert_eq!(select(&history, &options, &[]).as_deref(), Some("a"));
+            assert_eq!(select(&history, &options, &skip).as_deref(), Some("b"), "{:?}", policy);
+        }
+
+        let options = options(SelectionPolicy::Rotate);
+        let all: Vec<String> = feed.iter().map(|article| article.url.clone()).collect();
+        assert_eq!(select(&MemeHistory::default(), &options, &all), None);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("meme_history.json");
+        let options = MemeSelection { history_path: Some(path.clone()), ..options(SelectionPolicy::NewestUnseen) };
+
+        let mut history = MemeHistory::load(Some(&path));
+        assert!(history.current().is_none());
+        history.record(&article("a"), &options, time(0)).unwrap();
+
+        // Seen articles are remembered across restarts
+        let history = MemeHistory::load(Some(&path));
+        assert_eq!(history.current().unwrap().title, "a");
+        let selected = history.select(&[article("a"), article("b")], &[], &options, time(1)).unwrap();
+        assert_eq!(selected.title, "a");
+
+        fs::write(&path, "{ broken").unwrap();
+        assert!(MemeHistory::load(Some(&path)).current().is_none());
+
+        // A directory can't be read
+        assert!(MemeHistory::load(Some(dir.path())).current().is_none());
+        assert!(MemeHistory::load(Some(&dir.path().join("missing.json"))).current().is_none());
+    }
+
+    #[test]
+    fn test_in_memory_by_default() {
+        assert_eq!(MemeSelection::default().history_path, None);
+    }
+}
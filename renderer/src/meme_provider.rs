@@ -0,0 +1,163 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use knowyourmeme::{Article, KymClient, MemeEntry};
+use tracing::info;
+use url::Url;
+use crate::config::Config;
+use crate::content::{is_web_url, ContentItem, ContentProvider, ImageFit};
+use crate::meme_history::{MemeHistory, MemeSelection};
+
+/// Articles of the Know Your Meme front page, chosen by the selection policy
+pub struct MemeProvider {
+    client: KymClient,
+    selection: MemeSelection,
+    history: MemeHistory,
+    /// Article of the last item. It's recorded in the history once it was shown.
+    selected: Option<Article>,
+}
+
+impl MemeProvider {
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(MemeProvider {
+            client: KymClient::new(&config.knowyourmeme)?,
+            selection: config.meme_selection.clone(),
+            history: MemeHistory::load(config.meme_selection.history_path.as_deref()),
+            selected: None,
+        })
+    }
+}
+
+impl ContentProvider for MemeProvider {
+    fn name(&self) -> &'static str {
+        "knowyourmeme"
+    }
+
+    fn next_item(&mut self, now: DateTime<Utc>) -> Result<Option<ContentItem>> {
+        let feed = self.client.get_feed(&self.selection.feed)?;
+        let mut skipped = Vec::new();
+        let article = loop {
+            // The current article can be chosen without being on the front page, so it's skipped by its URL
+            let Some(mut article) = self.history.select(&feed, &skipped, &self.selection, now) else {
+                return Ok(None);
+            };
+            if article.meme_name.is_none() {
+                self.client.fill_meme(&mut article);
+            }
+
+            // The page and the entry of the article can make it unsuitable, so it's checked again
+            match self.selection.feed.safety.check(&article) {
+                Some(reason) => {
+                    info!(url = %article.url, %reason, "Skipping unsuitable article");
+                    skipped.push(article.url);
+                }
+                None => break article,
+            }
+        };
+        info!(url = %article.url, policy = ?self.selection.policy, "Selected Know Your Meme article");
+
+        let item = article_item(&article);
+        self.selected = Some(article);
+        Ok(Some(item))
+    }
+
+    fn current_item(&self) -> Option<ContentItem> {
+        // The safety filter might have been changed by a reload since the article was chosen
+        self.history.current()
+            .filter(|article| self.selection.feed.safety.allows(article))
+            .map(article_item)
+    }
+
+    fn shown(&mut self, item: &ContentItem, now: DateTime<Utc>) -> Result<()> {
+        let article = self.selected.iter()
+            .chain(self.history.current())
+            .find(|article| article.url == item.id)
+            .cloned();
+        match article {
+            Some(article) => self.history.record(&article, &self.selection, now),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Show the article with the meme name under its title.
+/// The image is left out unless it's a http(s) URL, since it's scraped from the page.
+pub fn article_item(article: &Article) -> ContentItem {
+    ContentItem {
+        id: article.url.clone(),
+        title: article.title.clone(),
+        subtitle: article.meme_name.clone(),
+        details: article.meme_entry.as_ref().and_then(meme_details),
+        body: article.summary.clone(),
+        image_url: Url::parse(&article.image_url).ok().filter(is_web_url).map(|_| article.image_url.clone()),
+        image_fit: ImageFit::default(),
+        dithering: None,
+    }
+}
+
+/// One line with the origin and the status of the meme, if the entry has them
+fn meme_details(entry: &MemeEntry) -> Option<String> {
+    let origin = match (&entry.origin, entry.year) {
+        (Some(origin), Some(year)) => Some(format!("Origin: {}, {}", origin, year)),
+        (Some(origin), None) => Some(format!("Origin: {}", origin)),
+        (None, Some(year)) => Some(format!("Origin: {}", year)),
+        (None, None) => None,
+    };
+    let status = entry.status.as_ref().map(|status| format!("Status: {}", status));
+
+    let parts: Vec<String> = origin.into_iter().chain(status).collect();
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts.join(" · "))
+}
+
+#[cfg(test)]
+mod tests {
+    use knowyourmeme::EntryType;
+    use super::*;
+
+    fn entry(origin: Option<&str>, year: Option<u16>, status: Option<&str>) -> MemeEntry {
+        MemeEntry {
+            url: "/memes/test-meme".to_string(),
+            name: "Test Meme".to_string(),
+            status: status.map(str::to_string),
+            types: Vec::new(),
+            year,
+            origin: origin.map(str::to_string),
+            tags: Vec::new(),
+            about: None,
+            image_url: None,
+        }
+    }
+
+    #[test]
+    fn test_article_item() {
+        let article = Article {
+            url: "/memes/test-meme".to_string(),
+            title: "Test Meme".to_string(),
+            summary: "A summary.".to_string(),
+            image_url: "https://i.kym-cdn.com/test.jpg".to_string(),
+            meme_name: Some("Test Meme".to_string()),
+            entry_type: EntryType::Meme,
+            tags: Vec::new(),
+            meme_entry: Some(entry(None, Some(2019), Some("Confirmed"))),
+            nsfw: false,
+        };
+
+        let item = article_item(&article);
+        assert_eq!(item.id, "/memes/test-meme");
+        assert_eq!(item.subtitle.as_deref(), Some("Test Meme"));
+        assert_eq!(item.details.as_deref(), Some("Origin: 2019 · Status: Confirmed"));
+        assert_eq!(item.body, "A summary.");
+        assert_eq!(item.image_url.as_deref(), Some("https://i.kym-cdn.com/test.jpg"));
+
+        // Scraped images can't point to local files
+        for image_url in ["file:///etc/passwd", "/test.jpg"] {
+            let article = Article { image_url: image_url.to_string(), ..article.clone() };
+            assert_eq!(article_item(&article).image_url, None, "{}", image_url);
+        }
+
+        assert_eq!(meme_details(&entry(Some("YouTube"), Some(2023), None)).as_deref(), Some("Origin: YouTube, 2023"));
+        assert_eq!(meme_details(&entry(None, None, None)), None);
+    }
+}
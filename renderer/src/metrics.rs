@@ -0,0 +1,147 @@
+use std::time::Instant;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use prometheus::{Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+
+/// Sources of fetched data, used as `source` label
+pub const SOURCE_CURRENT_WEATHER: &str = "current_weather";
+pub const SOURCE_WEATHER_FORECAST: &str = "weather_forecast";
+pub const SOURCE_CONTENT: &str = "content";
+
+/// Metrics of the render loop that are exported on `/metrics` in the Prometheus text format
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    fetch_duration: HistogramVec,
+    fetch_failures: IntCounterVec,
+    render_duration: Histogram,
+    uploads: IntCounterVec,
+    last_upload_success: Gauge,
+    last_render: Gauge,
+    weather_data_timestamp: Gauge,
+    weather_data_age: Gauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("renderer".to_string()), None).expect("Valid metrics prefix");
+
+        let fetch_duration = HistogramVec::new(
+            HistogramOpts::new("fetch_duration_seconds", "Time it took to fetch data by source"),
+            &["source"],
+        ).unwrap();
+        let fetch_failures = IntCounterVec::new(Opts::new("fetch_failures_total", "Failed fetches by source"), &["source"]).unwrap();
+        let render_duration = Histogram::with_opts(HistogramOpts::new("render_duration_seconds", "Time it took to render the image")).unwrap();
+        let uploads = IntCounterVec::new(Opts::new("uploads_total", "WebDAV uploads by status"), &["status"]).unwrap();
+        let last_upload_success = Gauge::new("last_upload_success_timestamp_seconds", "Unix time of the last successful upload").unwrap();
+        let last_render = Gauge::new("last_render_timestamp_seconds", "Unix time of the last successful render").unwrap();
+        let weather_data_timestamp = Gauge::new("weather_data_timestamp_seconds", "Unix time of the displayed current weather").unwrap();
+        let weather_data_age = Gauge::new("weather_data_age_seconds", "Age of the displayed current weather").unwrap();
+
+        // Names are unique, so registering can't fail
+        registry.register(Box::new(fetch_duration.clone())).unwrap();
+        registry.register(Box::new(fetch_failures.clone())).unwrap();
+        registry.register(Box::new(render_duration.clone())).unwrap();
+        registry.register(Box::new(uploads.clone())).unwrap();
+        registry.register(Box::new(last_upload_success.clone())).unwrap();
+        registry.register(Box::new(last_render.clone())).unwrap();
+        registry.register(Box::new(weather_data_timestamp.clone())).unwrap();
+        registry.register(Box::new(weather_data_age.clone())).unwrap();
+
+        // Show the failure counters with 0 before the first failure
+        for source in [SOURCE_CURRENT_WEATHER, SOURCE_WEATHER_FORECAST, SOURCE_CONTENT] {
+            fetch_failures.with_label_values(&[source]);
+        }
+
+        Metrics {
+            registry,
+            fetch_duration,
+            fetch_failures,
+            render_duration,
+            uploads,
+            last_upload_success,
+            last_render,
+            weather_data_timestamp,
+            weather_data_age,
+        }
+    }
+
+    /// Measure the time of the fetch and count it as failure if it returns an error
+    pub fn observe_fetch<T>(&self, source: &str, fetch: impl FnOnce() -> Result<T>) -> Result<T> {
+        let start = Instant::now();
+        let result = fetch();
+        self.fetch_duration.with_label_values(&[source]).observe(start.elapsed().as_secs_f64());
+        if result.is_err() {
+            self.fetch_failed(source);
+        }
+        result
+    }
+
+    /// Count a fetch as failure that didn't return an error, e.g. because of incomplete data
+    pub fn fetch_failed(&self, source: &str) {
+        self.fetch_failures.with_label_values(&[source]).inc();
+    }
+
+    pub fn observe_render<T>(&self, render: impl FnOnce() -> Result<T>) -> Result<T> {
+        let timer = self.render_duration.start_timer();
+        let result = render();
+        timer.observe_duration();
+        result
+    }
+
+    pub fn rendered(&self, rendered_at: DateTime<Utc>, weather_timestamp: DateTime<FixedOffset>) {
+        self.last_render.set(rendered_at.timestamp() as f64);
+        self.weather_data_timestamp.set(weather_timestamp.timestamp() as f64);
+    }
+
+    pub fn uploaded(&self, success: bool, at: DateTime<Utc>) {
+        let status = if success { "success" } else { "failure" };
+        self.uploads.with_label_values(&[status]).inc();
+        if success {
+            self.last_upload_success.set(at.timestamp() as f64);
+        }
+    }
+
+    /// Encode all metrics in the Prometheus text format
+    pub fn encode(&self, now: DateTime<Utc>) -> String {
+        let weather_timestamp = self.weather_data_timestamp.get();
+        if weather_timestamp > 0.0 {
+            self.weather_data_age.set(now.timestamp() as f64 - weather_timestamp);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).expect("Metrics can be encoded");
+        String::from_utf8(buffer).expect("Metrics are UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+
+        assert_eq!(metrics.observe_fetch(SOURCE_CONTENT, || Ok(1)).unwrap(), 1);
+        assert!(metrics.observe_fetch(SOURCE_CONTENT, || -> Result<()> { bail!("Offline") }).is_err());
+        metrics.uploaded(false, Utc::now());
+
+        let now = DateTime::parse_from_rfc3339("2024-12-24T08:10:00+01:00").unwrap();
+        metrics.rendered(now.to_utc(), DateTime::parse_from_rfc3339("2024-12-24T08:00:00+01:00").unwrap());
+
+        let text = metrics.encode(now.to_utc());
+        assert!(text.contains("renderer_fetch_failures_total{source=\"content\"} 1"));
+        assert!(text.contains("renderer_fetch_failures_total{source=\"current_weather\"} 0"));
+        assert!(text.contains("renderer_fetch_duration_seconds_count{source=\"content\"} 2"));
+        assert!(text.contains("renderer_uploads_total{status=\"failure\"} 1"));
+        assert!(text.contains("renderer_weather_data_age_seconds 600"));
+    }
+}
@@ -0,0 +1,267 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use tracing::info;
+use url::Url;
+use crate::content::{ContentItem, ContentProvider, ImageFit};
+use crate::dithering::DitherOptions;
+
+/// File extensions of the photos. Other files in the folder are ignored.
+const PHOTO_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "webp", "tif", "tiff"];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PhotoOptions {
+    /// Folder with the photos
+    pub path: PathBuf,
+    /// Show the photos in a random order instead of by file name. Every photo is shown once before one comes again.
+    #[serde(default)]
+    pub shuffle: bool,
+    /// How long every photo stays
+    #[serde(default = "default_rotate_hours")]
+    pub rotate_hours: u32,
+    #[serde(default = "default_fit")]
+    pub fit: ImageFit,
+    /// How the photos are dithered instead of `meme_dithering`
+    #[serde(default)]
+    pub dithering: Option<DitherOptions>,
+}
+
+fn default_rotate_hours() -> u32 {
+    1
+}
+
+fn default_fit() -> ImageFit {
+    ImageFit::Cover
+}
+
+/// A slideshow of the photos in a local folder.
+///
+/// Which photo is shown only depends on the time, so the slideshow continues where it was after a restart.
+/// The folder is read again for every image, so photos can be added and removed at any time.
+pub struct PhotoProvider {
+    options: PhotoOptions,
+    timezone: Tz,
+    current: Option<ContentItem>,
+}
+
+impl PhotoProvider {
+    pub fn new(options: &PhotoOptions, timezone: Tz) -> Self {
+        PhotoProvider { options: options.clone(), timezone, current: None }
+    }
+}
+
+impl ContentProvider for PhotoProvider {
+    fn name(&self) -> &'static str {
+        "photos"
+    }
+
+    fn next_item(&mut self, now: DateTime<Utc>) -> Result<Option<ContentItem>> {
+        let photos = list_photos(&self.options.path)?;
+        if photos.is_empty() {
+            bail!("No photos in {}", self.options.path.display());
+        }
+
+        // Rotate at full hours of the display, not of UTC
+        let local_seconds = now.with_timezone(&self.timezone).naive_local().and_utc().timestamp();
+        let slot = local_seconds.div_euclid(i64::from(self.options.rotate_hours.max(1)) * 3600) as u64;
+        let count = photos.len() as u64;
+        let position = (slot % count) as usize;
+        let index = match self.options.shuffle {
+            // Every round through the photos has its own order
+            true => shuffled(photos.len(), slot / count)[position],
+            false => position,
+        };
+
+        let mut item = photo_item(&photos[index], self.options.fit)?;
+        item.dithering = self.options.dithering.clone();
+        info!(path = %photos[index].display(), "Selected photo");
+
+        self.current = Some(item.clone());
+        Ok(Some(item))
+    }
+
+    fn current_item(&self) -> Option<ContentItem> {
+        self.current.clone()
+    }
+}
+
+/// Photos in the folder sorted by file name. Hidden files are skipped.
+fn list_photos(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut photos: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Couldn't read photo folder {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| !path.file_name().and_then(OsStr::to_str).is_some_and(|name| name.starts_with('.')))
+        .filter(|path| {
+            path.extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|extension| PHOTO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        })
+        .collect();
+    photos.sort();
+    Ok(photos)
+}
+
+/// Show the photo with its caption. Without a caption, the photo takes the whole panel.
+fn photo_item(photo: &Path, fit: ImageFit) -> Result<ContentItem> {
+    let path = photo.canonicalize().with_context(|| format!("Couldn't read photo {}", photo.display()))?;
+    let image_url = Url::from_file_path(&path).map_err(|_| anyhow!("Invalid photo path {}", path.display()))?;
+    let (title, body) = caption(&path);
+
+    Ok(ContentItem {
+        id: path.display().to_string(),
+        title,
+        subtitle: None,
+        details: None,
+        body,
+        image_url: Some(image_url.to_string()),
+        image_fit: fit,
+        dithering: None,
+    })
+}
+
+/// Title and text of the photo from `<name>.txt` next to it or from the EXIF image description.
+/// The first line of the text file is the title, the other lines are the text.
+fn caption(photo: &Path) -> (String, String) {
+    if let Ok(text) = fs::read_to_string(photo.with_extension("txt")) {
+        let mut lines = text.lines();
+        let title = lines.next().unwrap_or_default().trim().to_string();
+        let body = lines.flat_map(str::split_whitespace).collect::<Vec<_>>().join(" ");
+        return (title, body);
+    }
+
+    (exif_description(photo).unwrap_or_default(), String::new())
+}
+
+fn exif_description(photo: &Path) -> Option<String> {
+    let file = File::open(photo).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut BufReader::new(file)).ok()?;
+    let field = exif.get_field(exif::Tag::ImageDescription, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Ascii(values) => values.first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+            .filter(|description| !description.is_empty()),
+        _ => None,
+    }
+}
+
+/// Order of `len` items that only depends on the seed
+fn shuffled(len: usize, seed: u64) -> Vec<usize> {
+    // SplitMix64 is good enough to shuffle photos and doesn't need a dependency
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+
+    // Fisher-Yates
+    let mut order: Vec<usize> = (0..len).collect();
+    for i in (1..len).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::Cursor;
+    use image::{ImageFormat, RgbImage};
+    use super::*;
+
+    fn time(hours: i64) -> DateTime<Utc> {
+        // Midnight in Berlin. The hours since 1970 are a multiple of 3, so a round of 3 photos starts here.
+        DateTime::parse_from_rfc3339("2024-12-23T23:00:00Z").unwrap().to_utc() + chrono::Duration::hours(hours)
+    }
+
+    fn write_photo(path: &Path) {
+        RgbImage::new(4, 3).save(path).unwrap();
+    }
+
+    /// JPEG with an EXIF image description in an APP1 segment after the start of image marker
+    fn write_photo_with_description(path: &Path, description: &str) {
+        let mut jpeg = Cursor::new(Vec::new());
+        RgbImage::new(4, 3).write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+        let jpeg = jpeg.into_inner();
+
+        let field = exif::Field {
+            tag: exif::Tag::ImageDescription,
+            ifd_num: exif::In::PRIMARY,
+            value: exif::Value::Ascii(vec![description.as_bytes().to_vec()]),
+        };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&field);
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend_from_slice(&[0xFF, 0xE1]);
+        bytes.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        bytes.extend_from_slice(b"Exif\0\0");
+        bytes.extend_from_slice(&tiff);
+        bytes.extend_from_slice(&jpeg[2..]);
+        fs::write(path, bytes).unwrap();
+    }
+
+    fn names(provider: &mut PhotoProvider, hours: impl Iterator<Item = i64>) -> Vec<String> {
+        hours.map(|hour| {
+            let item = provider.next_item(time(hour)).unwrap().unwrap();
+            Path::new(&item.id).file_name().unwrap().to_string_lossy().to_string()
+        }).collect()
+    }
+
+    #[test]
+    fn test_slideshow() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.png", "a.PNG", "c.jpg"] {
+            write_photo(&dir.path().join(name));
+        }
+        fs::write(dir.path().join("notes.md"), "Not a photo").unwrap();
+        fs::write(dir.path().join(".hidden.png"), "Not a photo either").unwrap();
+
+        let options = PhotoOptions { path: dir.path().to_path_buf(), shuffle: false, rotate_hours: 2, fit: ImageFit::Cover, dithering: None };
+        let mut provider = PhotoProvider::new(&options, chrono_tz::Europe::Berlin);
+        assert_eq!(names(&mut provider, 0..8), ["a.PNG", "a.PNG", "b.png", "b.png", "c.jpg", "c.jpg", "a.PNG", "a.PNG"]);
+
+        // Every photo comes once per round, but in a different order every round
+        let options = PhotoOptions { shuffle: true, rotate_hours: 1, ..options };
+        let mut provider = PhotoProvider::new(&options, chrono_tz::Europe::Berlin);
+        let shown = names(&mut provider, 0..30);
+        for round in shown.chunks(3) {
+            assert_eq!(round.iter().collect::<HashSet<_>>().len(), 3, "{:?}", shown);
+        }
+        assert!(shown.chunks(3).collect::<HashSet<_>>().len() > 1, "{:?}", shown);
+
+        let empty = tempfile::tempdir().unwrap();
+        let mut provider = PhotoProvider::new(&PhotoOptions { path: empty.path().to_path_buf(), ..options }, chrono_tz::Europe::Berlin);
+        assert!(provider.next_item(time(0)).is_err());
+    }
+
+    #[test]
+    fn test_captions() {
+        let dir = tempfile::tempdir().unwrap();
+        write_photo(&dir.path().join("beach.png"));
+        fs::write(dir.path().join("beach.txt"), "Beach\nSummer holidays\n  in 2023\n").unwrap();
+        write_photo_with_description(&dir.path().join("sunset.jpg"), "Sunset");
+        write_photo(&dir.path().join("plain.png"));
+
+        let caption = |name: &str| photo_item(&dir.path().join(name), ImageFit::Cover).map(|item| (item.title, item.body)).unwrap();
+        assert_eq!(caption("beach.png"), ("Beach".to_string(), "Summer holidays in 2023".to_string()));
+        assert_eq!(caption("sunset.jpg"), ("Sunset".to_string(), String::new()));
+        assert_eq!(caption("plain.png"), (String::new(), String::new()));
+
+        let item = photo_item(&dir.path().join("plain.png"), ImageFit::Contain).unwrap();
+        assert!(item.image_url.unwrap().starts_with("file://"));
+        assert_eq!(item.image_fit, ImageFit::Contain);
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use crate::content::{ContentItem, ContentProvider, ImageFit};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct QuoteOptions {
+    /// Text file with one quote per paragraph. A last line starting with a dash is the author.
+    pub path: PathBuf,
+    /// Shown above every quote
+    #[serde(default = "default_title")]
+    pub title: String,
+}
+
+fn default_title() -> String {
+    "Quote of the day".to_string()
+}
+
+/// A different quote of the file every day. The file is read again for every image, so it can be changed at any time.
+pub struct QuoteProvider {
+    options: QuoteOptions,
+    timezone: Tz,
+    current: Option<ContentItem>,
+}
+
+impl QuoteProvider {
+    pub fn new(options: &QuoteOptions, timezone: Tz) -> Self {
+        QuoteProvider { options: options.clone(), timezone, current: None }
+    }
+}
+
+impl ContentProvider for QuoteProvider {
+    fn name(&self) -> &'static str {
+        "quotes"
+    }
+
+    fn next_item(&mut self, now: DateTime<Utc>) -> Result<Option<ContentItem>> {
+        let path = &self.options.path;
+        let text = fs::read_to_string(path).with_context(|| format!("Couldn't read quotes from {}", path.display()))?;
+        let quotes = parse_quotes(&text, &self.options.title);
+        if quotes.is_empty() {
+            bail!("No quotes in {}", path.display());
+        }
+
+        // The day changes at midnight of the display, not of UTC
+        let day = now.with_timezone(&self.timezone).num_days_from_ce() as usize;
+        let mut quote = quotes[day % quotes.len()].clone();
+        quote.id = format!("{}#{}", path.display(), quote.id);
+
+        self.current = Some(quote.clone());
+        Ok(Some(quote))
+    }
+
+    fn current_item(&self) -> Option<ContentItem> {
+        self.current.clone()
+    }
+}
+
+/// Split the text into quotes at empty lines. Lines starting with `#` are comments.
+/// The id of every quote is its index.
+fn parse_quotes(text: &str, title: &str) -> Vec<ContentItem> {
+    let lines: Vec<&str> = text.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+
+    lines.split(|line| line.is_empty())
+        .filter(|paragraph| !paragraph.is_empty())
+        .enumerate()
+        .map(|(i, paragraph)| {
+            let (author, quote) = match paragraph.split_last() {
+                Some((last, quote)) if !quote.is_empty() && is_author(last) => {
+                    (Some(last.trim_start_matches(['—', '–', '-', ' ']).to_string()), quote)
+                }
+                _ => (None, paragraph),
+            };
+
+            ContentItem {
+                id: i.to_string(),
+                title: title.to_string(),
+                subtitle: author,
+                details: None,
+                body: quote.join(" "),
+                image_url: None,
+                image_fit: ImageFit::default(),
+                dithering: None,
+            }
+        })
+        .collect()
+}
+
+fn is_author(line: &str) -> bool {
+    line.starts_with(['—', '–', '-'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTES: &str = "# Quotes for the hallway\n\
+        The best way out\n  is always through.\n— Robert Frost\n\
+        \n\n\
+        Simplicity is prerequisite for reliability.\n\
+        - Edsger W. Dijkstra\n\
+        \n\
+        Anonymous wisdom.\n";
+
+    #[test]
+    fn test_parse_quotes() {
+        let quotes = parse_quotes(QUOTES, "Quote of the day");
+        assert_eq!(quotes.len(), 3);
+        assert_eq!(quotes[0].body, "The best way out is always through.");
+        assert_eq!(quotes[0].subtitle.as_deref(), Some("Robert Frost"));
+        assert_eq!(quotes[0].title, "Quote of the day");
+        assert_eq!(quotes[1].subtitle.as_deref(), Some("Edsger W. Dijkstra"));
+        assert_eq!(quotes[2].body, "Anonymous wisdom.");
+        assert_eq!(quotes[2].subtitle, None);
+    }
+
+    #[test]
+    fn test_quote_of_the_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quotes.txt");
+        fs::write(&path, QUOTES).unwrap();
+        let options = QuoteOptions { path: path.clone(), title: default_title() };
+        let mut provider = QuoteProvider::new(&options, chrono_tz::Europe::Berlin);
+
+        let time = |rfc3339: &str| DateTime::parse_from_rfc3339(rfc3339).unwrap().to_utc();
+        let first = provider.next_item(time("2024-12-24T12:00:00Z")).unwrap().unwrap();
+        // Still the same day in Berlin
+        let same_day = provider.next_item(time("2024-12-24T22:30:00Z")).unwrap().unwrap();
+        assert_eq!(first, same_day);
+        // Already the next day in Berlin
+        let next_day = provider.next_item(time("2024-12-24T23:30:00Z")).unwrap().unwrap();
+        assert_ne!(first.id, next_day.id);
+
+        fs::write(&path, "# Nothing here\n").unwrap();
+        assert!(provider.next_item(time("2024-12-25T12:00:00Z")).is_err());
+        assert_eq!(provider.current_item(), Some(next_day));
+    }
+}
@@ -1,6 +1,6 @@
 use std::cmp::{min, max};
 use chrono::Timelike;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use image::{Rgb, RgbImage};
 use rusttype::Font;
 use embedded_graphics::prelude::*;
@@ -15,7 +15,8 @@ use brightsky::Condition;
 use crate::text::measure_text;
 use crate::DisplayData;
 use crate::drawing::*;
-use crate::config::CONFIG;
+use crate::config::Config;
+use crate::images::ImageLoader;
 
 pub type EpdBuffer = Vec<u8>;
 
@@ -27,7 +28,7 @@ const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
 const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
 const RED: Rgb<u8> = Rgb([255, 0, 0]);
 
-pub fn render_image(display_data: DisplayData) -> Result<EpdBuffer> {
+pub fn render_image(display_data: DisplayData, config: &Config, images: &ImageLoader) -> Result<RgbImage> {
     let font = Font::try_from_bytes(FONT_DATA).expect("Failed to open font");
 
     let graph_x = 50i64;
@@ -49,7 +50,7 @@ pub fn render_image(display_data: DisplayData) -> Result<EpdBuffer> {
     let max_rain = weather.week_max_rain();
     let max_rain_scale = max((1.2 * max_rain).ceil() as i32, 5);
 
-    let graph = draw_graph(weather, min_temp_scale, max_temp_scale, max_rain_scale, graph_width, graph_height, &font);
+    let graph = draw_graph(&display_data, config, min_temp_scale, max_temp_scale, max_rain_scale, graph_width, graph_height, &font);
     //let daily_temps = weather.daily_minmax_temps();
 
     let mut image = RgbImage::from_fn(800, 480, |_, _| -> Rgb<u8> { Rgb([255u8, 255u8, 255u8]) });
@@ -61,7 +62,7 @@ pub fn render_image(display_data: DisplayData) -> Result<EpdBuffer> {
     let temp_text = format!("{}°", current_temp);
     let temp_color = if current_temp < 27.0 { BLACK } else { RED };
 
-    let mut current_time = chrono::Utc::now().with_timezone(&CONFIG.timezone);
+    let mut current_time = display_data.date_time;
     if current_time.minute() > 30 {
         current_time = current_time.with_hour((current_time.hour() + 1) % 23).unwrap();
     }
@@ -78,7 +79,7 @@ pub fn render_image(display_data: DisplayData) -> Result<EpdBuffer> {
     //     }
     // }
 
-    let time_text = format!("{}", current_time.format(CONFIG.time_format));
+    let time_text = format!("{}", current_time.format(&config.time_format));
 
     let (temp_width, temp_height) = measure_text(&font, &temp_text, temp_size);
     let desc_x = temp_x + temp_width + 20.0;
@@ -92,14 +93,14 @@ pub fn render_image(display_data: DisplayData) -> Result<EpdBuffer> {
     let condition = weather.current_weather.condition.as_ref().unwrap_or(&Condition::Null);
 
     let condition_text = match condition {
-        Condition::Dry => &CONFIG.weather_conditions.dry,
-        Condition::Fog => &CONFIG.weather_conditions.fog,
-        Condition::Rain => &CONFIG.weather_conditions.rain,
-        Condition::Sleet => &CONFIG.weather_conditions.sleet,
-        Condition::Snow => &CONFIG.weather_conditions.snow,
-        Condition::Hail => &CONFIG.weather_conditions.hail,
-        Condition::Thunderstorm => &CONFIG.weather_conditions.thunderstorm,
-        Condition::Null => &CONFIG.weather_conditions.null,
+        Condition::Dry => &config.weather_conditions.dry,
+        Condition::Fog => &config.weather_conditions.fog,
+        Condition::Rain => &config.weather_conditions.rain,
+        Condition::Sleet => &config.weather_conditions.sleet,
+        Condition::Snow => &config.weather_conditions.snow,
+        Condition::Hail => &config.weather_conditions.hail,
+        Condition::Thunderstorm => &config.weather_conditions.thunderstorm,
+        Condition::Null => &config.weather_conditions.null,
     }.to_string();
 
     let humidity_icon = load_icon(HUMIDITY_ICON_DATA)?;
@@ -153,16 +154,10 @@ pub fn render_image(display_data: DisplayData) -> Result<EpdBuffer> {
     // Draw graph
     image::imageops::overlay(&mut image, &graph, graph_x, graph_y);
 
-    let meme_y = graph_y + graph_height;
-    draw_meme(&mut image, &font, display_data.kym_article, meme_y);
-    
-    // Save image as PNG in debug mode
-    if cfg!(debug_assertions) {
-        image.save("output.png").context("Couldn't save image")?;
-    }
+    let content_y = graph_y + graph_height;
+    draw_content(&mut image, &font, display_data.content, content_y, &config.meme_dithering, images);
 
-    let epd_buffer = rgb_image_to_epd_image(image);
-    Ok(epd_buffer)
+    Ok(image)
 }
 
 fn load_icon(data: &[u8]) -> Result<RgbImage> {
@@ -170,7 +165,7 @@ fn load_icon(data: &[u8]) -> Result<RgbImage> {
     Ok(img)
 }
 
-fn rgb_image_to_epd_image(image: RgbImage) -> EpdBuffer {
+pub fn rgb_image_to_epd_image(image: &RgbImage) -> EpdBuffer {
     let mut buffer = vec![TriColor::White.get_byte_value(); buffer_len(EPD_WIDTH as usize, 2 * EPD_HEIGHT as usize)];
     let mut display = VarDisplay::<TriColor>::new(EPD_WIDTH, EPD_HEIGHT, &mut buffer, true).expect("Failed to create display");
 
@@ -186,3 +181,202 @@ fn rgb_image_to_epd_image(image: RgbImage) -> EpdBuffer {
 
     buffer
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use chrono::DateTime;
+    use chrono_tz::Tz;
+    use brightsky::{CurrentWeather, HourlyWeather};
+    use build_utils::format::ConfigFormat;
+    use build_utils::overrides::Environment;
+    use url::Url;
+    use crate::clock::{Clock, FixedClock};
+    use crate::content::{ContentItem, ImageFit};
+    use crate::weather::WeatherData;
+    use super::*;
+
+    /// Set this environment variable to overwrite the golden files with the current output:
+    /// `UPDATE_GOLDEN=1 cargo test -p renderer`
+    const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+    fn test_config() -> Config {
+        let json = fs::read_to_string(test_dir("fixtures").join("config.json")).unwrap();
+        Config::parse(&json, ConfigFormat::Json5, &Environment::default()).unwrap()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join(name)
+    }
+
+    fn load_fixture<T: serde::de::DeserializeOwned>(name: &str) -> T {
+        let path = test_dir("fixtures").join(name);
+        let json = fs::read_to_string(&path).unwrap_or_else(|e| panic!("Couldn't read {:?}: {}", path, e));
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        serde_json::from_value(value["weather"].clone()).unwrap()
+    }
+
+    fn fixture_item() -> ContentItem {
+        let image_url = Url::from_file_path(test_dir("fixtures").join("article.png")).unwrap();
+
+        ContentItem {
+            id: "/memes/test-meme".to_string(),
+            title: "Test Meme Gets Rendered On An E-Paper Display".to_string(),
+            subtitle: Some("Test Meme".to_string()),
+            details: None,
+            body: "This summary is long enough to wrap over multiple lines so that the text layout of the meme panel gets covered by the golden image as well.".to_string(),
+            image_url: Some(image_url.to_string()),
+            image_fit: ImageFit::default(),
+            dithering: None,
+        }
+    }
+
+    /// The frozen clock all golden images are rendered at
+    fn frozen_time(timezone: &Tz) -> DateTime<Tz> {
+        FixedClock::parse("2024-12-24T08:00", timezone).unwrap().now_in(timezone)
+    }
+
+    fn render_fixture(current_weather: &CurrentWeather, weather_forecast: &Vec<HourlyWeather>, content: Option<ContentItem>) -> RgbImage {
+        let config = test_config();
+        let display_data = DisplayData {
+            date_time: frozen_time(&config.timezone),
+            weather: WeatherData {
+                current_weather,
+                weather_forecast,
+            },
+            content,
+        };
+
+        let images = ImageLoader::new(&config.images).unwrap();
+        render_image(display_data, &config, &images).expect("Rendering failed")
+    }
+
+    fn fixture_weather() -> (CurrentWeather, Vec<HourlyWeather>) {
+        (load_fixture("current_weather.json"), load_fixture("hourly_weather.json"))
+    }
+
+    /// Render the fixture weather with the content and compare it against the golden files `name`
+    fn check_golden(name: &str, content: Option<ContentItem>) {
+        let (current_weather, weather_forecast) = fixture_weather();
+        assert_golden(name, &render_fixture(&current_weather, &weather_forecast, content));
+    }
+
+    /// Compare the image and its EPD buffer against the golden files.
+    /// On a mismatch the actual image and a diff image are written next to the golden files.
+    fn assert_golden(name: &str, image: &RgbImage) {
+        let dir = test_dir("golden");
+        let png_path = dir.join(format!("{}.png", name));
+        let buffer_path = dir.join(format!("{}.bin", name));
+        let actual_path = dir.join(format!("{}.actual.png", name));
+        let diff_path = dir.join(format!("{}.diff.png", name));
+
+        let buffer = rgb_image_to_epd_image(image);
+
+        if env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+            fs::create_dir_all(&dir).unwrap();
+            image.save(&png_path).unwrap();
+            fs::write(&buffer_path, &buffer).unwrap();
+            let _ = fs::remove_file(&actual_path);
+            let _ = fs::remove_file(&diff_path);
+            return;
+        }
+
+        let expected = image::open(&png_path)
+            .unwrap_or_else(|e| panic!("Couldn't open golden image {:?} ({}). Run with {}=1 to create it.", png_path, e, UPDATE_GOLDEN_ENV))
+            .to_rgb8();
+
+        if expected.dimensions() != image.dimensions() {
+            image.save(&actual_path).unwrap();
+            panic!("Golden image {} has size {:?}, got {:?}", name, expected.dimensions(), image.dimensions());
+        }
+
+        let different_pixels = expected.pixels().zip(image.pixels()).filter(|(e, a)| e != a).count();
+        if different_pixels > 0 {
+            image.save(&actual_path).unwrap();
+            diff_image(&expected, image).save(&diff_path).unwrap();
+            panic!(
+                "{} pixels differ from golden image {}. See {:?} and {:?}. Run with {}=1 to accept the changes.",
+                different_pixels, name, actual_path, diff_path, UPDATE_GOLDEN_ENV
+            );
+        }
+
+        let expected_buffer = fs::read(&buffer_path)
+            .unwrap_or_else(|e| panic!("Couldn't read golden buffer {:?} ({}). Run with {}=1 to create it.", buffer_path, e, UPDATE_GOLDEN_ENV));
+        assert!(expected_buffer == buffer, "EPD buffer differs from golden buffer {}", name);
+    }
+
+    /// Fade the expected image and mark every differing pixel red
+    fn diff_image(expected: &RgbImage, actual: &RgbImage) -> RgbImage {
+        RgbImage::from_fn(expected.width(), expected.height(), |x, y| {
+            let e = expected.get_pixel(x, y);
+            if e != actual.get_pixel(x, y) {
+                return RED;
+            }
+            let gray = ((e[0] as u16 + e[1] as u16 + e[2] as u16) / 3 / 4 + 191) as u8;
+            Rgb([gray, gray, gray])
+        })
+    }
+
+    #[test]
+    fn golden_with_article() {
+        check_golden("with_article", Some(fixture_item()));
+    }
+
+    #[test]
+    fn golden_with_meme_entry() {
+        let item = ContentItem { details: Some("Origin: 2019 · Status: Confirmed".to_string()), ..fixture_item() };
+        check_golden("with_meme_entry", Some(item));
+    }
+
+    #[test]
+    fn golden_without_image() {
+        let item = ContentItem {
+            id: "quotes.txt#0".to_string(),
+            title: "Quote of the day".to_string(),
+            subtitle: Some("Robert Frost".to_string()),
+            details: None,
+            body: "The best way out is always through.".to_string(),
+            image_url: None,
+            image_fit: ImageFit::default(),
+            dithering: None,
+        };
+
+        check_golden("without_image", Some(item));
+    }
+
+    #[test]
+    fn golden_photo() {
+        // Without a caption the photo takes the whole panel
+        let item = ContentItem {
+            id: "article.png".to_string(),
+            title: String::new(),
+            subtitle: None,
+            details: None,
+            body: String::new(),
+            image_fit: ImageFit::Contain,
+            ..fixture_item()
+        };
+
+        check_golden("photo", Some(item));
+    }
+
+    #[test]
+    fn golden_broken_image() {
+        // A missing image is replaced by a placeholder instead of failing the render
+        let image_url = Url::from_file_path(test_dir("fixtures").join("missing.png")).unwrap();
+        let item = ContentItem { image_url: Some(image_url.to_string()), ..fixture_item() };
+        check_golden("broken_image", Some(item));
+    }
+
+    #[test]
+    fn golden_without_article_hot() {
+        let (mut current_weather, weather_forecast) = fixture_weather();
+        current_weather.temperature = Some(31.4);
+        current_weather.condition = Some(Condition::Thunderstorm);
+        current_weather.relative_humidity = None;
+
+        assert_golden("without_article_hot", &render_fixture(&current_weather, &weather_forecast, None));
+    }
+}
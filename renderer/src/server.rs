@@ -0,0 +1,332 @@
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use anyhow::{anyhow, Result};
+use build_utils::schedule::Schedule;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use image::{ImageFormat, RgbImage};
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use tracing::{error, info};
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::rendering::{rgb_image_to_epd_image, EpdBuffer};
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// How late a scheduled refresh may be before `/healthz` fails
+const HEALTH_GRACE_PERIOD_MINUTES: i64 = 10;
+/// How old the current weather may be when an image is rendered before `/healthz` fails.
+/// Bright Sky's current weather is usually less than an hour old, older data means that it couldn't be fetched.
+const MAX_WEATHER_AGE_MINUTES: i64 = 120;
+
+/// The latest rendered image in all formats that are served
+pub struct RenderedImage {
+    pub epd_buffer: EpdBuffer,
+    pub png: Vec<u8>,
+    pub rendered_at: DateTime<Utc>,
+    /// Time of the current weather on the image
+    pub weather_at: DateTime<Utc>,
+    /// Hex encoded SHA-256 of the EPD buffer
+    pub checksum: String,
+}
+
+impl RenderedImage {
+    pub fn new(image: &RgbImage, rendered_at: DateTime<Utc>, weather_at: DateTime<Utc>) -> Result<Self> {
+        let epd_buffer = rgb_image_to_epd_image(image);
+
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+        let checksum = format!("{:x}", Sha256::digest(&epd_buffer));
+
+        Ok(RenderedImage { epd_buffer, png, rendered_at, weather_at, checksum })
+    }
+}
+
+/// Shared slot for the latest rendered image.
+/// The render loop writes to it and the HTTP server reads from it.
+#[derive(Clone, Default)]
+pub struct ImageStore(Arc<RwLock<Option<Arc<RenderedImage>>>>);
+
+impl ImageStore {
+    pub fn set(&self, image: RenderedImage) {
+        *self.0.write().unwrap() = Some(Arc::new(image));
+    }
+
+    pub fn get(&self) -> Option<Arc<RenderedImage>> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+/// Decides whether the served image is too old
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    pub schedule: Schedule,
+    pub timezone: Tz,
+    /// Fixed maximum age. Without it, the image is outdated when a scheduled refresh is late.
+    pub max_age: Option<Duration>,
+}
+
+impl HealthCheck {
+    pub fn from_config(config: &Config) -> Self {
+        HealthCheck {
+            schedule: config.schedule.clone(),
+            timezone: config.timezone,
+            max_age: config.max_image_age_minutes.map(|minutes| Duration::minutes(minutes.into())),
+        }
+    }
+
+    /// Check the time of the latest render and the age of its weather data and return why it isn't healthy.
+    /// Renders keep going with the last weather when it can't be fetched, so a fresh image can still show old data.
+    pub fn check(&self, image: Option<&RenderedImage>, now: DateTime<Utc>) -> Result<(), String> {
+        let image = image.ok_or("No image has been rendered yet")?;
+        let rendered_at = image.rendered_at;
+
+        let weather_age = rendered_at - image.weather_at;
+        if weather_age > Duration::minutes(MAX_WEATHER_AGE_MINUTES) {
+            return Err(format!(
+                "The image shows weather from {}, which was {} minutes old when it was rendered",
+                image.weather_at.to_rfc3339(), weather_age.num_minutes()
+            ));
+        }
+
+        let outdated_at = match self.max_age {
+            Some(max_age) => rendered_at + max_age,
+            // Long pauses of the schedule like quiet hours don't count as outdated
+            None => self.schedule.next_after(&rendered_at.with_timezone(&self.timezone)).with_timezone(&Utc)
+                + Duration::minutes(HEALTH_GRACE_PERIOD_MINUTES),
+        };
+
+        if now > outdated_at {
+            return Err(format!("The image was rendered at {} and is outdated since {}", rendered_at.to_rfc3339(), outdated_at.to_rfc3339()));
+        }
+        Ok(())
+    }
+}
+
+/// Start the HTTP server in a background thread and return the address it listens on.
+///
+/// Routes:
+/// - `/<image_name>`: EPD buffer for the display
+/// - `/preview.png`: PNG of the same image
+/// - `/status.json`: Information about the latest render
+/// - `/metrics`: Metrics in the Prometheus text format
+/// - `/healthz`: `200` if the image is up to date, `503` otherwise
+pub fn spawn(address: &str, image_name: String, store: ImageStore, metrics: Metrics, health_check: Arc<RwLock<HealthCheck>>) -> Result<SocketAddr> {
+    let server = Server::http(address).map_err(|e| anyhow!("Couldn't start HTTP server on {}: {}", address, e))?;
+    let address = server.server_addr().to_ip().ok_or(anyhow!("HTTP server isn't listening on an IP address"))?;
+    info!("Serving image on http://{}/{}", address, image_name);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            if let Err(e) = handle_request(request, &image_name, &store, &metrics, &health_check) {
+                error!(error = %e, "Couldn't respond to HTTP request");
+            }
+        }
+    });
+
+    Ok(address)
+}
+
+fn handle_request(request: Request, image_name: &str, store: &ImageStore, metrics: &Metrics, health_check: &RwLock<HealthCheck>) -> std::io::Result<()> {
+    if request.method() != &Method::Get && request.method() != &Method::Head {
+        return request.respond(Response::empty(405));
+    }
+
+    // Ignore query parameters
+    let path = request.url().split('?').next().unwrap_or_default().trim_start_matches('/').to_string();
+
+    let image = store.get();
+
+    match (path.as_str(), image) {
+        ("status.json", image) => {
+            let status = match image {
+                Some(image) => serde_json::json!({
+                    "image_name": image_name,
+                    "rendered_at": image.rendered_at.to_rfc3339(),
+                    "age_seconds": (Utc::now() - image.rendered_at).num_seconds(),
+                    "weather_at": image.weather_at.to_rfc3339(),
+                    "size": image.epd_buffer.len(),
+                    "sha256": image.checksum,
+                }),
+                None => serde_json::json!({
+                    "image_name": image_name,
+                    "rendered_at": null,
+                }),
+            };
+            let response = Response::from_string(status.to_string())
+                .with_header(header("Content-Type", "application/json"))
+                .with_header(header("Cache-Control", "no-cache"));
+            request.respond(response)
+        }
+        ("metrics", _) => {
+            let response = Response::from_string(metrics.encode(Utc::now()))
+                .with_header(header("Content-Type", "text/plain; version=0.0.4"));
+            request.respond(response)
+        }
+        ("healthz", image) => {
+            let response = match health_check.read().unwrap().check(image.as_deref(), Utc::now()) {
+                Ok(()) => Response::from_string("OK"),
+                Err(reason) => Response::from_string(reason).with_status_code(503),
+            };
+            request.respond(response.with_header(header("Cache-Control", "no-cache")))
+        }
+        ("preview.png", Some(image)) => {
+            let etag = format!("\"{}-png\"", image.checksum);
+            respond_cached(request, &image.png, "image/png", &etag, image.rendered_at)
+        }
+        (path, Some(image)) if path == image_name => {
+            let etag = format!("\"{}\"", image.checksum);
+            respond_cached(request, &image.epd_buffer, "application/octet-stream", &etag, image.rendered_at)
+        }
+        // Nothing has been rendered yet
+        ("preview.png", None) => request.respond(Response::empty(503)),
+        (path, None) if path == image_name => request.respond(Response::empty(503)),
+        _ => request.respond(Response::empty(404)),
+    }
+}
+
+/// Respond with the body or with `304 Not Modified` if the client already has this version
+fn respond_cached(request: Request, body: &[u8], content_type: &str, etag: &str, last_modified: DateTime<Utc>) -> std::io::Result<()> {
+    let last_modified_text = last_modified.format(HTTP_DATE_FORMAT).to_string();
+
+    let not_modified = match get_header(&request, "If-None-Match") {
+        Some(value) => value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"),
+        None => get_header(&request, "If-Modified-Since")
+            .and_then(|value| NaiveDateTime::parse_from_str(&value, HTTP_DATE_FORMAT).ok())
+            .is_some_and(|since| last_modified.timestamp() <= since.and_utc().timestamp()),
+    };
+
+    let headers = [
+        header("Content-Type", content_type),
+        header("ETag", etag),
+        header("Last-Modified", &last_modified_text),
+        header("Cache-Control", "no-cache"),
+    ];
+
+    if not_modified {
+        let mut response = Response::empty(StatusCode(304));
+        for h in headers {
+            response.add_header(h);
+        }
+        return request.respond(response);
+    }
+
+    // tiny_http leaves out the body of HEAD requests by itself
+    let mut response = Response::from_data(body.to_vec());
+    for h in headers {
+        response.add_header(h);
+    }
+    request.respond(response)
+}
+
+fn get_header(request: &Request, name: &str) -> Option<String> {
+    request.headers().iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.to_string())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("Invalid header")
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::blocking::Client;
+    use reqwest::StatusCode;
+    use super::*;
+
+    fn health_check() -> HealthCheck {
+        HealthCheck {
+            schedule: serde_json::from_value(serde_json::json!({ "cron": "0 * * * *", "quiet_hours": { "start": "23:00", "end": "06:00" } })).unwrap(),
+            timezone: chrono_tz::Europe::Berlin,
+            max_age: None,
+        }
+    }
+
+    #[test]
+    fn test_serve_image() {
+        let store = ImageStore::default();
+        let address = spawn("127.0.0.1:0", "esp_image.img".to_string(), store.clone(), Metrics::new(), Arc::new(RwLock::new(health_check()))).unwrap();
+        let url = |path: &str| format!("http://{}/{}", address, path);
+        let client = Client::new();
+
+        // Nothing rendered yet
+        let response = client.get(url("esp_image.img")).send().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = client.get(url("healthz")).send().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let image = RgbImage::from_pixel(800, 480, image::Rgb([255, 255, 255]));
+        store.set(RenderedImage::new(&image, Utc::now(), Utc::now()).unwrap());
+
+        let response = client.get(url("esp_image.img")).send().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()["ETag"].to_str().unwrap().to_string();
+        assert!(response.headers().contains_key("Last-Modified"));
+        assert_eq!(response.bytes().unwrap().len(), store.get().unwrap().epd_buffer.len());
+
+        // Unchanged image
+        let response = client.get(url("esp_image.img")).header("If-None-Match", &etag).send().unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = client.get(url("preview.png")).send().unwrap();
+        assert_eq!(response.headers()["Content-Type"], "image/png");
+
+        let status: serde_json::Value = client.get(url("status.json")).send().unwrap().json().unwrap();
+        assert_eq!(status["sha256"], store.get().unwrap().checksum);
+
+        let response = client.get(url("healthz")).send().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let metrics = client.get(url("metrics")).send().unwrap().text().unwrap();
+        assert!(metrics.contains("renderer_fetch_failures_total"));
+
+        let response = client.get(url("unknown")).send().unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn rendered(rendered_at: DateTime<Utc>, weather_at: DateTime<Utc>) -> RenderedImage {
+        RenderedImage::new(&RgbImage::new(800, 480), rendered_at, weather_at).unwrap()
+    }
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_health_check() {
+        let check = health_check();
+
+        let image = rendered(time("2024-12-24T08:00:05+01:00"), time("2024-12-24T07:30:00+01:00"));
+        assert!(check.check(Some(&image), time("2024-12-24T09:05:00+01:00")).is_ok());
+        assert!(check.check(Some(&image), time("2024-12-24T09:15:00+01:00")).is_err());
+
+        // No refresh is expected during the quiet hours
+        let image = rendered(time("2024-12-24T22:00:05+01:00"), time("2024-12-24T21:30:00+01:00"));
+        assert!(check.check(Some(&image), time("2024-12-25T05:00:00+01:00")).is_ok());
+        assert!(check.check(Some(&image), time("2024-12-25T06:30:00+01:00")).is_err());
+
+        let check = HealthCheck { max_age: Some(Duration::minutes(30)), ..health_check() };
+        assert!(check.check(Some(&image), time("2024-12-24T22:45:00+01:00")).is_err());
+        assert!(check.check(None, time("2024-12-24T22:45:00+01:00")).is_err());
+    }
+
+    #[test]
+    fn test_health_check_stale_weather() {
+        let check = health_check();
+
+        // Renders are on time, but the weather couldn't be fetched since 05:00
+        let image = rendered(time("2024-12-24T08:00:05+01:00"), time("2024-12-24T05:00:00+01:00"));
+        let reason = check.check(Some(&image), time("2024-12-24T08:05:00+01:00")).unwrap_err();
+        assert!(reason.contains("180 minutes old"), "{}", reason);
+
+        let image = rendered(time("2024-12-24T08:00:05+01:00"), time("2024-12-24T06:30:00+01:00"));
+        assert!(check.check(Some(&image), time("2024-12-24T08:05:00+01:00")).is_ok());
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
+use signal_hook::flag;
+use signal_hook::iterator::Signals;
+use tracing::info;
+
+/// What the render loop should do because of a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// SIGTERM or SIGINT: Stop after the current render
+    Shutdown,
+    /// SIGHUP or SIGUSR1: Reload the config and render a new image now
+    Reload,
+}
+
+/// Signals received while the render loop was busy or waiting
+pub struct SignalEvents(Receiver<Event>);
+
+impl SignalEvents {
+    /// Handle signals in a background thread.
+    ///
+    /// A second SIGTERM or SIGINT exits immediately, e.g. to abort an upload that hangs.
+    pub fn listen() -> Result<Self> {
+        let shutdown_requested = Arc::new(AtomicBool::new(false));
+        for signal in [SIGTERM, SIGINT] {
+            // Registered first, so that it only exits if the flag was set by an earlier signal
+            flag::register_conditional_shutdown(signal, 1, shutdown_requested.clone())?;
+            flag::register(signal, shutdown_requested.clone())?;
+        }
+
+        let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP, SIGUSR1]).context("Couldn't register signal handlers")?;
+        let (sender, events) = Self::channel();
+
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                let event = match signal {
+                    SIGHUP | SIGUSR1 => Event::Reload,
+                    _ => Event::Shutdown,
+                };
+                info!(signal, ?event, "Received signal");
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(events)
+    }
+
+    /// Events that are sent by hand instead of by signals
+    pub fn channel() -> (Sender<Event>, Self) {
+        let (sender, receiver) = mpsc::channel();
+        (sender, SignalEvents(receiver))
+    }
+
+    /// Event that was received in the meantime, if any
+    pub fn pending(&self) -> Option<Event> {
+        self.0.try_recv().ok()
+    }
+
+    /// Wait until the timeout is over or an event is received.
+    /// Returns `None` if the timeout is over.
+    pub fn wait(&self, timeout: Duration) -> Option<Event> {
+        match self.0.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) => None,
+            // No more signals can arrive, so just sleep
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(timeout);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use super::*;
+
+    #[test]
+    fn test_wait() {
+        let (sender, events) = SignalEvents::channel();
+        assert_eq!(events.pending(), None);
+
+        let start = Instant::now();
+        assert_eq!(events.wait(Duration::from_millis(50)), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Events interrupt the wait
+        sender.send(Event::Reload).unwrap();
+        let start = Instant::now();
+        assert_eq!(events.wait(Duration::from_secs(3600)), Some(Event::Reload));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        sender.send(Event::Shutdown).unwrap();
+        assert_eq!(events.pending(), Some(Event::Shutdown));
+    }
+}
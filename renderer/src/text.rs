@@ -2,6 +2,7 @@ use imageproc::drawing::Canvas;
 use image::{RgbImage, Rgb};
 use rusttype::{point, Font, PositionedGlyph, Rect, Scale};
 use std::cmp::max;
+use tracing::debug;
 
 // Code mostly taken wholesale from
 // https://github.com/image-rs/imageproc/blob/master/src/drawing/text.rs
@@ -86,7 +87,7 @@ pub fn adjust_scale_to_fit_box(
 
         // Cancel if text scale got too small
         if scale < 13.0 {
-            println!("Text scale is too small. Returning {}", scale);
+            debug!(scale, "Text scale is too small");
             break;
         }
     }
@@ -117,7 +118,7 @@ pub fn adjust_scale_to_fit_lines(
 
         // Cancel if text scale got too small
         if scale < 15.0 {
-            println!("Text scale is too small. Returning {}. Target line_count: {}, got: {}", scale, line_count, text_line_count);
+            debug!(scale, line_count, text_line_count, "Text scale is too small");
             break;
         }
     }
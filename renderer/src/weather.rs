@@ -75,7 +75,7 @@ impl WeatherData<'_> {
     
             let mut sum_temp = 0.0;
             let mut sum_prep = 0.0;
-            let window_start = if i >= WINDOW_SIZE / 2 { i - WINDOW_SIZE / 2 } else { 0 };
+            let window_start = i.saturating_sub(WINDOW_SIZE / 2);
             let window_end = if i + WINDOW_SIZE / 2 < data.len() { i + WINDOW_SIZE / 2 } else { data.len() - 1 };
     
             for item in data[window_start..=window_end].iter() {
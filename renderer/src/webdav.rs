@@ -0,0 +1,359 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, bail, Context, Result};
+use md5::Md5;
+use reqwest::blocking::{Client, Response};
+use reqwest::{Method, StatusCode, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+/// Timeout of every request, so that a hanging upload doesn't block the render loop or a shutdown
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    #[default]
+    Basic,
+    Digest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebDavAuth {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub method: AuthMethod,
+}
+
+/// Minimal WebDAV client for uploading the image
+pub struct WebDavClient {
+    client: Client,
+    base_url: Url,
+    auth: Option<WebDavAuth>,
+}
+
+impl WebDavClient {
+    pub fn new(base_url: &str, auth: Option<WebDavAuth>) -> Result<Self> {
+        let mut base_url = Url::parse(base_url).context("Invalid WebDAV URL")?;
+
+        // Make sure the URL is treated as a directory when joining
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(WebDavClient { client, base_url, auth })
+    }
+
+    /// Upload the file so that readers never see a partially written file.
+    ///
+    /// Missing directories are created, the data is written to a temporary file first
+    /// and then moved to its final name. Afterwards the upload is verified by size and checksum.
+    pub fn upload(&self, name: &str, data: &[u8]) -> Result<Url> {
+        let url = self.base_url.join(name)?;
+        let temp_url = self.base_url.join(&format!("{}.part", name))?;
+
+        self.create_directories(&url)?;
+
+        let response = self.send(Method::PUT, &temp_url, &[], Some(data))?;
+        check_status(&response, "Upload")?;
+
+        let headers = [
+            ("Destination", url.to_string()),
+            ("Overwrite", "T".to_string()),
+        ];
+        let response = self.send(Method::from_bytes(b"MOVE")?, &temp_url, &headers, None)?;
+        check_status(&response, "Moving the uploaded file")?;
+
+        self.verify(&url, data)?;
+
+        Ok(url)
+    }
+
+    /// Create all directories between the base URL and the file that don't exist yet
+    fn create_directories(&self, file_url: &Url) -> Result<()> {
+        // Collect missing directories from the deepest up to the base URL
+        let mut missing = Vec::new();
+        let mut dir = file_url.join("./")?;
+        loop {
+            if self.exists(&dir)? {
+                break;
+            }
+            missing.push(dir.clone());
+
+            if dir == self.base_url || dir.path() == "/" {
+                break;
+            }
+            dir = dir.join("../")?;
+        }
+
+        for dir in missing.iter().rev() {
+            info!(%dir, "Creating WebDAV directory");
+            let response = self.send(Method::from_bytes(b"MKCOL")?, dir, &[], None)?;
+            check_status(&response, "Creating directory")?;
+        }
+
+        Ok(())
+    }
+
+    fn exists(&self, url: &Url) -> Result<bool> {
+        let response = self.send(Method::from_bytes(b"PROPFIND")?, url, &[("Depth", "0".to_string())], None)?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => bail!("Couldn't check if {} exists. Status code: {}", url, status),
+        }
+    }
+
+    /// Check that the server has the same file as we uploaded
+    fn verify(&self, url: &Url, data: &[u8]) -> Result<()> {
+        let response = self.send(Method::HEAD, url, &[], None)?;
+        check_status(&response, "Verifying the upload")?;
+
+        let size = response.headers().get("Content-Length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if let Some(size) = size {
+            if size != data.len() {
+                bail!("Uploaded file has size {}, expected {}", size, data.len());
+            }
+        }
+
+        let response = self.send(Method::GET, url, &[], None)?;
+        check_status(&response, "Downloading the uploaded file")?;
+        let downloaded = response.bytes()?;
+
+        if Sha256::digest(&downloaded) != Sha256::digest(data) {
+            bail!("Checksum of the uploaded file doesn't match");
+        }
+
+        Ok(())
+    }
+
+    /// Send a request with authentication.
+    /// For digest authentication the request is repeated after the server sent its challenge.
+    fn send(&self, method: Method, url: &Url, headers: &[(&str, String)], body: Option<&[u8]>) -> Result<Response> {
+        let build = |authorization: Option<String>| {
+            let mut request = self.client.request(method.clone(), url.clone());
+            for (name, value) in headers {
+                request = request.header(*name, value);
+            }
+            if let Some(body) = body {
+                request = request.body(body.to_vec());
+            }
+
+            match (&self.auth, authorization) {
+                (_, Some(authorization)) => request.header("Authorization", authorization),
+                (Some(auth), None) if auth.method == AuthMethod::Basic => request.basic_auth(&auth.username, Some(&auth.password)),
+                _ => request,
+            }
+        };
+
+        let response = build(None).send()?;
+
+        let auth = match &self.auth {
+            Some(auth) if auth.method == AuthMethod::Digest && response.status() == StatusCode::UNAUTHORIZED => auth,
+            _ => return Ok(response),
+        };
+
+        let challenge = response.headers().get_all("WWW-Authenticate").iter()
+            .filter_map(|v| v.to_str().ok())
+            .find(|v| v.to_ascii_lowercase().starts_with("digest "))
+            .ok_or(anyhow!("Server doesn't support digest authentication"))?;
+
+        let authorization = digest_authorization(auth, method.as_str(), url, challenge, &new_cnonce())?;
+        Ok(build(Some(authorization)).send()?)
+    }
+}
+
+fn check_status(response: &Response, action: &str) -> Result<()> {
+    if !response.status().is_success() {
+        bail!("{} failed for {}. Status code: {}", action, response.url(), response.status());
+    }
+    Ok(())
+}
+
+/// Build the `Authorization` header for a digest challenge (RFC 7616).
+/// Supports the MD5 and SHA-256 algorithms with `qop=auth` or without qop.
+fn digest_authorization(auth: &WebDavAuth, method: &str, url: &Url, challenge: &str, cnonce: &str) -> Result<String> {
+    let params = parse_challenge(&challenge["digest ".len()..]);
+    let param = |name: &str| params.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+
+    let realm = param("realm").ok_or(anyhow!("Digest challenge has no realm"))?;
+    let nonce = param("nonce").ok_or(anyhow!("Digest challenge has no nonce"))?;
+    let algorithm = param("algorithm").unwrap_or("MD5");
+    let qop = param("qop").map(|qop| qop.split(',').map(str::trim).find(|q| *q == "auth"));
+
+    let hash: fn(&str) -> String = match algorithm.to_ascii_uppercase().as_str() {
+        "MD5" => |s| format!("{:x}", Md5::digest(s.as_bytes())),
+        "SHA-256" => |s| format!("{:x}", Sha256::digest(s.as_bytes())),
+        _ => bail!("Unsupported digest algorithm: {}", algorithm),
+    };
+
+    let uri = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let nc = "00000001";
+
+    let ha1 = hash(&format!("{}:{}:{}", auth.username, realm, auth.password));
+    let ha2 = hash(&format!("{}:{}", method, uri));
+
+    let mut header = format!(r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={}"#, auth.username, realm, nonce, uri, algorithm);
+
+    match qop {
+        Some(Some(qop)) => {
+            let response = hash(&format!("{}:{}:{}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2));
+            header += &format!(r#", qop={}, nc={}, cnonce="{}", response="{}""#, qop, nc, cnonce, response);
+        }
+        Some(None) => bail!("Server requires an unsupported digest qop"),
+        None => {
+            let response = hash(&format!("{}:{}:{}", ha1, nonce, ha2));
+            header += &format!(r#", response="{}""#, response);
+        }
+    }
+
+    if let Some(opaque) = param("opaque") {
+        header += &format!(r#", opaque="{}""#, opaque);
+    }
+
+    Ok(header)
+}
+
+/// Parse `key=value` pairs of a challenge. Values can be quoted and contain commas.
+fn parse_challenge(text: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = text.trim();
+
+    while let Some((name, value)) = rest.split_once('=') {
+        let name = name.trim().trim_start_matches(',').trim().to_string();
+        let value = value.trim_start();
+
+        let (value, remaining) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+        } else {
+            let end = value.find(',').unwrap_or(value.len());
+            (&value[..end], &value[end..])
+        };
+
+        params.push((name, value.to_string()));
+        rest = remaining;
+    }
+
+    params
+}
+
+/// Client nonce for digest authentication. It only needs to be unpredictable enough to not repeat.
+fn new_cnonce() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let hash = Sha256::digest(format!("{}:{}", nanos, std::process::id()).as_bytes());
+    format!("{:x}", hash)[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tiny_http::{Response, Server};
+    use super::*;
+
+    #[test]
+    fn test_digest_rfc2617_example() {
+        let auth = WebDavAuth {
+            username: "Mufasa".to_string(),
+            password: "Circle Of Life".to_string(),
+            method: AuthMethod::Digest,
+        };
+        let challenge = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+        let url = Url::parse("http://www.nowhere.org/dir/index.html").unwrap();
+
+        let header = digest_authorization(&auth, "GET", &url, challenge, "0a4f113b").unwrap();
+
+        assert!(header.contains(r#"response="6629fae49393a05397450978507c4ef1""#), "{}", header);
+        assert!(header.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+    }
+
+    type Files = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Serve a tiny in-memory WebDAV share that requires basic authentication
+    fn spawn_webdav_server() -> (String, Files) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let files: Files = Arc::new(Mutex::new(HashMap::new()));
+        files.lock().unwrap().insert("/webdav/".to_string(), Vec::new());
+
+        let server_files = files.clone();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let authorized = request.headers().iter()
+                    .any(|h| h.field.equiv("Authorization") && h.value.as_str() == "Basic dXNlcjpzZWNyZXQ="); // user:secret
+                if !authorized {
+                    request.respond(Response::empty(401)).unwrap();
+                    continue;
+                }
+
+                let path = request.url().to_string();
+                let mut files = server_files.lock().unwrap();
+                let status = match request.method().as_str() {
+                    "PROPFIND" if files.contains_key(&path) => 207,
+                    "PROPFIND" => 404,
+                    "MKCOL" => { files.insert(path, Vec::new()); 201 }
+                    "PUT" => {
+                        let mut body = Vec::new();
+                        request.as_reader().read_to_end(&mut body).unwrap();
+                        files.insert(path, body);
+                        201
+                    }
+                    "MOVE" => {
+                        let destination = request.headers().iter().find(|h| h.field.equiv("Destination")).unwrap().value.to_string();
+                        let destination = Url::parse(&destination).unwrap().path().to_string();
+                        let body = files.remove(&path).unwrap();
+                        files.insert(destination, body);
+                        201
+                    }
+                    "GET" | "HEAD" if files.contains_key(&path) => {
+                        let body = files[&path].clone();
+                        request.respond(Response::from_data(body)).unwrap();
+                        continue;
+                    }
+                    "GET" | "HEAD" => 404,
+                    _ => 405,
+                };
+                request.respond(Response::empty(status)).unwrap();
+            }
+        });
+
+        (format!("http://{}/webdav/images", address), files)
+    }
+
+    #[test]
+    fn test_upload() {
+        let (url, files) = spawn_webdav_server();
+        let auth = WebDavAuth {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+            method: AuthMethod::Basic,
+        };
+        let data = vec![1u8, 2, 3, 4];
+
+        let client = WebDavClient::new(&url, Some(auth)).unwrap();
+        let uploaded_url = client.upload("esp_image.img", &data).unwrap();
+
+        assert!(uploaded_url.path().ends_with("/webdav/images/esp_image.img"));
+        let files = files.lock().unwrap();
+        assert!(files.contains_key("/webdav/images/"));
+        assert_eq!(files["/webdav/images/esp_image.img"], data);
+        assert!(!files.contains_key("/webdav/images/esp_image.img.part"));
+    }
+
+    #[test]
+    fn test_upload_unauthorized() {
+        let (url, _files) = spawn_webdav_server();
+
+        let client = WebDavClient::new(&url, None).unwrap();
+        assert!(client.upload("esp_image.img", &[1, 2, 3]).is_err());
+    }
+}
@@ -1,7 +1,26 @@
 #!/bin/sh
 
+# The shell is PID 1 in the container, so signals have to be forwarded to the app.
+# SIGTERM and SIGINT stop it gracefully, SIGHUP and SIGUSR1 reload its config.
+stopping=0
+trap 'stopping=1; kill -TERM "$pid" 2>/dev/null' TERM INT
+trap 'kill -HUP "$pid" 2>/dev/null' HUP
+trap 'kill -USR1 "$pid" 2>/dev/null' USR1
+
 while true; do
-    /usr/local/bin/app
-    echo "App crashed with exit code $?. Restarting..." >&2
+    /usr/local/bin/app "$@" &
+    pid=$!
+
+    # wait returns early when a signal is trapped, so keep waiting until the app has exited
+    while kill -0 "$pid" 2>/dev/null; do
+        wait "$pid"
+        code=$?
+    done
+
+    if [ "$stopping" = 1 ]; then
+        exit "$code"
+    fi
+
+    echo "App crashed with exit code $code. Restarting..." >&2
     sleep 10
 done